@@ -1,22 +1,25 @@
-use const_format::formatcp;
-use itertools::Itertools as _;
+use std::borrow::Borrow;
+
+use futures_util::Stream;
 use serde::Serialize;
 use serde_with::skip_serializing_none;
 
 use crate::{
     api_request::ApiError,
+    fields::{self, AnimeField},
     objects::{
-        AnimeList, AnimeNode, AnimeRankingType, AnimeSeasonSort, AnimeSingleList, RankingList,
-        SeasonList, SeasonType,
+        AnimeId, AnimeItem, AnimeList, AnimeNode, AnimeRankItem, AnimeRankingList,
+        AnimeRankingType, AnimeSeasonSort, AnimeSingleList, SeasonList, SeasonType,
+        SingleAnimeItem,
     },
-    MalClient, API_URL,
+    pagination, MalClient,
 };
 
-const ANIME_URL: &str = formatcp!("{API_URL}/anime");
-const ANIME_ID: &str = formatcp!("{API_URL}/anime/{{ANIME_ID}}");
-const ANIME_RANKING: &str = formatcp!("{API_URL}/anime/ranking");
-const ANIME_SEASON: &str = formatcp!("{API_URL}/anime/season/{{YEAR}}/{{SEASON}}");
-const ANIME_SUGGESTIONS: &str = formatcp!("{API_URL}/anime/suggestions");
+const ANIME_URL: &str = "/anime";
+const ANIME_ID: &str = "/anime/{ANIME_ID}";
+const ANIME_RANKING: &str = "/anime/ranking";
+const ANIME_SEASON: &str = "/anime/season/{YEAR}/{SEASON}";
+const ANIME_SUGGESTIONS: &str = "/anime/suggestions";
 
 #[derive(Debug, Clone)]
 pub struct AnimeApi<'a> {
@@ -151,10 +154,9 @@ impl<'a> AnimeListGet<'a> {
         self
     }
 
-    pub fn fields<I: IntoIterator<Item = impl AsRef<str>>>(mut self, fields: I) -> Self {
-        let fields = fields.into_iter().map(|f| f.as_ref().to_string()).join(",");
-
-        self.fields = Some(fields);
+    /// The fields to return. See [AnimeField].
+    pub fn fields<I: IntoIterator<Item = impl Borrow<AnimeField>>>(mut self, fields: I) -> Self {
+        self.fields = Some(fields::join::<AnimeField>(fields));
         self
     }
 
@@ -164,12 +166,15 @@ impl<'a> AnimeListGet<'a> {
         self
     }
 
-    /// Send the request.
-    pub async fn send(self) -> Result<AnimeList, ApiError> {
-        let query = serde_qs::to_string(&self)?;
+    fn url(&self) -> Result<String, ApiError> {
+        let query = serde_qs::to_string(self)?;
 
-        let url = format!("{ANIME_URL}?{query}");
+        Ok(format!("{}?{query}", self.client.api_url(ANIME_URL)))
+    }
 
+    /// Send the request.
+    pub async fn send(self) -> Result<AnimeList, ApiError> {
+        let url = self.url()?;
         self.client.api_request().get(url, false).await
     }
 
@@ -178,6 +183,29 @@ impl<'a> AnimeListGet<'a> {
     pub fn send_blocking(self) -> Result<AnimeList, ApiError> {
         crate::RUNTIME.block_on(self.send())
     }
+
+    /// Stream every item of the list, lazily fetching the next page when the current one runs out.
+    pub fn into_stream(self) -> impl Stream<Item = Result<AnimeItem, ApiError>> + 'a {
+        let url = self.url();
+        pagination::stream::<AnimeList>(self.client, url, false)
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    pub async fn collect_all(self, max: Option<usize>) -> Result<Vec<AnimeItem>, ApiError> {
+        pagination::collect_all(self.into_stream(), max).await
+    }
+
+    /// Iterate over every item of the list, fetching the next page when the current one runs out.
+    #[cfg(feature = "blocking")]
+    pub fn into_iter_blocking(self) -> impl Iterator<Item = Result<AnimeItem, ApiError>> + 'a {
+        pagination::blocking_iter(self.into_stream())
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    #[cfg(feature = "blocking")]
+    pub fn collect_all_blocking(self, max: Option<usize>) -> Result<Vec<AnimeItem>, ApiError> {
+        crate::RUNTIME.block_on(self.collect_all(max))
+    }
 }
 
 /// GET anime details.
@@ -189,30 +217,33 @@ pub struct AnimeDetailsGet<'a> {
     #[serde(skip)]
     client: &'a MalClient,
     #[serde(skip)]
-    anime_id: Option<u64>,
+    anime_id: Option<AnimeId>,
 
     fields: Option<String>,
 }
 
 impl<'a> AnimeDetailsGet<'a> {
     /// The anime id.
-    pub fn anime_id(mut self, id: u64) -> Self {
-        self.anime_id = Some(id);
+    pub fn anime_id(mut self, id: impl Into<AnimeId>) -> Self {
+        self.anime_id = Some(id.into());
         self
     }
 
-    pub fn fields<I: IntoIterator<Item = impl AsRef<str>>>(mut self, fields: I) -> Self {
-        let fields = fields.into_iter().map(|f| f.as_ref().to_string()).join(",");
-
-        self.fields = Some(fields);
+    /// The fields to return. See [AnimeField].
+    pub fn fields<I: IntoIterator<Item = impl Borrow<AnimeField>>>(mut self, fields: I) -> Self {
+        self.fields = Some(fields::join::<AnimeField>(fields));
         self
     }
 
     /// Send the request.
     pub async fn send(self) -> Result<AnimeNode, ApiError> {
-        assert!(self.anime_id.is_some(), "anime_id is a required param");
+        let Some(anime_id) = self.anime_id else {
+            return Err(ApiError::MissingParameter("anime_id"));
+        };
 
-        let url = ANIME_ID.replace("{ANIME_ID}", &self.anime_id.unwrap().to_string());
+        let url = self
+            .client
+            .api_url(&ANIME_ID.replace("{ANIME_ID}", &anime_id.to_string()));
         let query = serde_qs::to_string(&self)?;
         let url = format!("{url}?{query}");
 
@@ -261,31 +292,56 @@ impl<'a> AnimeRankingGet<'a> {
         self
     }
 
-    pub fn fields<I: IntoIterator<Item = impl AsRef<str>>>(mut self, fields: I) -> Self {
-        let fields = fields.into_iter().map(|f| f.as_ref().to_string()).join(",");
-
-        self.fields = Some(fields);
+    /// The fields to return. See [AnimeField].
+    pub fn fields<I: IntoIterator<Item = impl Borrow<AnimeField>>>(mut self, fields: I) -> Self {
+        self.fields = Some(fields::join::<AnimeField>(fields));
         self
     }
 
-    /// Send the request.
-    pub async fn send(self) -> Result<RankingList, ApiError> {
-        assert!(
-            self.ranking_type.is_some(),
-            "ranking_type is a required param"
-        );
+    fn url(&self) -> Result<String, ApiError> {
+        if self.ranking_type.is_none() {
+            return Err(ApiError::MissingParameter("ranking_type"));
+        }
 
-        let query = serde_qs::to_string(&self)?;
-        let url = format!("{ANIME_RANKING}?{query}");
+        let query = serde_qs::to_string(self)?;
+
+        Ok(format!("{}?{query}", self.client.api_url(ANIME_RANKING)))
+    }
 
+    /// Send the request.
+    pub async fn send(self) -> Result<AnimeRankingList, ApiError> {
+        let url = self.url()?;
         self.client.api_request().get(url, false).await
     }
 
     /// Send the request.
     #[cfg(feature = "blocking")]
-    pub fn send_blocking(self) -> Result<RankingList, ApiError> {
+    pub fn send_blocking(self) -> Result<AnimeRankingList, ApiError> {
         crate::RUNTIME.block_on(self.send())
     }
+
+    /// Stream every item of the list, lazily fetching the next page when the current one runs out.
+    pub fn into_stream(self) -> impl Stream<Item = Result<AnimeRankItem, ApiError>> + 'a {
+        let url = self.url();
+        pagination::stream::<AnimeRankingList>(self.client, url, false)
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    pub async fn collect_all(self, max: Option<usize>) -> Result<Vec<AnimeRankItem>, ApiError> {
+        pagination::collect_all(self.into_stream(), max).await
+    }
+
+    /// Iterate over every item of the list, fetching the next page when the current one runs out.
+    #[cfg(feature = "blocking")]
+    pub fn into_iter_blocking(self) -> impl Iterator<Item = Result<AnimeRankItem, ApiError>> + 'a {
+        pagination::blocking_iter(self.into_stream())
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    #[cfg(feature = "blocking")]
+    pub fn collect_all_blocking(self, max: Option<usize>) -> Result<Vec<AnimeRankItem>, ApiError> {
+        crate::RUNTIME.block_on(self.collect_all(max))
+    }
 }
 
 /// GET seasonal anime.
@@ -339,10 +395,9 @@ impl<'a> AnimeSeasonalGet<'a> {
         self
     }
 
-    pub fn fields<I: IntoIterator<Item = impl AsRef<str>>>(mut self, fields: I) -> Self {
-        let fields = fields.into_iter().map(|f| f.as_ref().to_string()).join(",");
-
-        self.fields = Some(fields);
+    /// The fields to return. See [AnimeField].
+    pub fn fields<I: IntoIterator<Item = impl Borrow<AnimeField>>>(mut self, fields: I) -> Self {
+        self.fields = Some(fields::join::<AnimeField>(fields));
         self
     }
 
@@ -352,19 +407,27 @@ impl<'a> AnimeSeasonalGet<'a> {
         self
     }
 
-    /// Send the request.
-    pub async fn send(self) -> Result<SeasonList, ApiError> {
-        assert!(self.year.is_some(), "year is a required param");
-        assert!(self.season.is_some(), "season is a required param");
+    fn url(&self) -> Result<String, ApiError> {
+        let Some(year) = self.year else {
+            return Err(ApiError::MissingParameter("year"));
+        };
+        let Some(season) = &self.season else {
+            return Err(ApiError::MissingParameter("season"));
+        };
 
-        let query = serde_qs::to_string(&self)?;
-        let season: &str = self.season.unwrap().into();
-        let url = ANIME_SEASON
-            .replace("{YEAR}", &self.year.unwrap().to_string())
-            .replace("{SEASON}", season);
+        let query = serde_qs::to_string(self)?;
+        let url = self.client.api_url(
+            &ANIME_SEASON
+                .replace("{YEAR}", &year.to_string())
+                .replace("{SEASON}", &season.to_string()),
+        );
 
-        let url = format!("{url}?{query}");
+        Ok(format!("{url}?{query}"))
+    }
 
+    /// Send the request.
+    pub async fn send(self) -> Result<SeasonList, ApiError> {
+        let url = self.url()?;
         self.client.api_request().get(url, false).await
     }
 
@@ -373,6 +436,34 @@ impl<'a> AnimeSeasonalGet<'a> {
     pub fn send_blocking(self) -> Result<SeasonList, ApiError> {
         crate::RUNTIME.block_on(self.send())
     }
+
+    /// Stream every item of the list, lazily fetching the next page when the current one runs out.
+    pub fn into_stream(self) -> impl Stream<Item = Result<SingleAnimeItem, ApiError>> + 'a {
+        let url = self.url();
+        pagination::stream::<SeasonList>(self.client, url, false)
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    pub async fn collect_all(self, max: Option<usize>) -> Result<Vec<SingleAnimeItem>, ApiError> {
+        pagination::collect_all(self.into_stream(), max).await
+    }
+
+    /// Iterate over every item of the list, fetching the next page when the current one runs out.
+    #[cfg(feature = "blocking")]
+    pub fn into_iter_blocking(
+        self,
+    ) -> impl Iterator<Item = Result<SingleAnimeItem, ApiError>> + 'a {
+        pagination::blocking_iter(self.into_stream())
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    #[cfg(feature = "blocking")]
+    pub fn collect_all_blocking(
+        self,
+        max: Option<usize>,
+    ) -> Result<Vec<SingleAnimeItem>, ApiError> {
+        crate::RUNTIME.block_on(self.collect_all(max))
+    }
 }
 
 /// GET suggested anime.
@@ -404,10 +495,9 @@ impl<'a> AnimeSuggestedGet<'a> {
         self
     }
 
-    pub fn fields<I: IntoIterator<Item = impl AsRef<str>>>(mut self, fields: I) -> Self {
-        let fields = fields.into_iter().map(|f| f.as_ref().to_string()).join(",");
-
-        self.fields = Some(fields);
+    /// The fields to return. See [AnimeField].
+    pub fn fields<I: IntoIterator<Item = impl Borrow<AnimeField>>>(mut self, fields: I) -> Self {
+        self.fields = Some(fields::join::<AnimeField>(fields));
         self
     }
 
@@ -417,10 +507,18 @@ impl<'a> AnimeSuggestedGet<'a> {
         self
     }
 
+    fn url(&self) -> Result<String, ApiError> {
+        let query = serde_qs::to_string(self)?;
+
+        Ok(format!(
+            "{}?{query}",
+            self.client.api_url(ANIME_SUGGESTIONS)
+        ))
+    }
+
     /// Send the request.
     pub async fn send(self) -> Result<AnimeSingleList, ApiError> {
-        let query = serde_qs::to_string(&self)?;
-        let url = format!("{ANIME_SUGGESTIONS}?{query}");
+        let url = self.url()?;
         self.client.api_request().get(url, true).await
     }
 
@@ -429,4 +527,32 @@ impl<'a> AnimeSuggestedGet<'a> {
     pub fn send_blocking(self) -> Result<AnimeSingleList, ApiError> {
         crate::RUNTIME.block_on(self.send())
     }
+
+    /// Stream every item of the list, lazily fetching the next page when the current one runs out.
+    pub fn into_stream(self) -> impl Stream<Item = Result<SingleAnimeItem, ApiError>> + 'a {
+        let url = self.url();
+        pagination::stream::<AnimeSingleList>(self.client, url, true)
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    pub async fn collect_all(self, max: Option<usize>) -> Result<Vec<SingleAnimeItem>, ApiError> {
+        pagination::collect_all(self.into_stream(), max).await
+    }
+
+    /// Iterate over every item of the list, fetching the next page when the current one runs out.
+    #[cfg(feature = "blocking")]
+    pub fn into_iter_blocking(
+        self,
+    ) -> impl Iterator<Item = Result<SingleAnimeItem, ApiError>> + 'a {
+        pagination::blocking_iter(self.into_stream())
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    #[cfg(feature = "blocking")]
+    pub fn collect_all_blocking(
+        self,
+        max: Option<usize>,
+    ) -> Result<Vec<SingleAnimeItem>, ApiError> {
+        crate::RUNTIME.block_on(self.collect_all(max))
+    }
 }
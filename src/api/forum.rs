@@ -1,17 +1,18 @@
-use const_format::formatcp;
+use futures_util::Stream;
 use serde::Serialize;
 use serde_with::skip_serializing_none;
 
-
 use crate::{
     api_request::ApiError,
-    objects::{ForumBoards, ForumSort, ForumTopics, TopicDetail},
-    MalClient, API_URL, 
+    objects::{
+        BoardId, ForumBoards, ForumSort, ForumTopic, ForumTopics, Post, TopicDetail, TopicId,
+    },
+    pagination, MalClient,
 };
 
-pub const FORUM_BOARDS: &str = formatcp!("{API_URL}/forum/boards");
-pub const FORUM_TID: &str = formatcp!("{API_URL}/forum/topic/{{TOPIC_ID}}");
-pub const FORUM_TOPICS: &str = formatcp!("{API_URL}/forum/topics");
+pub const FORUM_BOARDS: &str = "/forum/boards";
+pub const FORUM_TID: &str = "/forum/topic/{TOPIC_ID}";
+pub const FORUM_TOPICS: &str = "/forum/topics";
 
 #[derive(Debug)]
 pub struct ForumApi<'a> {
@@ -89,7 +90,10 @@ pub struct ForumApiGetBoards<'a> {
 impl<'a> ForumApiGetBoards<'a> {
     /// Send the request.
     pub async fn send(self) -> Result<ForumBoards, ApiError> {
-        self.client.api_request().get(FORUM_BOARDS, false).await
+        self.client
+            .api_request()
+            .get(self.client.api_url(FORUM_BOARDS), false)
+            .await
     }
 
     /// Send the request.
@@ -105,7 +109,7 @@ pub struct ForumApiGetTopicDetail<'a> {
     #[serde(skip)]
     client: &'a MalClient,
     #[serde(skip)]
-    topic_id: Option<u64>,
+    topic_id: Option<TopicId>,
 
     limit: Option<u8>,
     offset: Option<u64>,
@@ -113,8 +117,8 @@ pub struct ForumApiGetTopicDetail<'a> {
 
 impl<'a> ForumApiGetTopicDetail<'a> {
     /// The topic id. This parameter is required.
-    pub fn topic_id(mut self, id: u64) -> Self {
-        self.topic_id = Some(id);
+    pub fn topic_id(mut self, id: impl Into<TopicId>) -> Self {
+        self.topic_id = Some(id.into());
         self
     }
 
@@ -131,14 +135,22 @@ impl<'a> ForumApiGetTopicDetail<'a> {
         self
     }
 
-    /// Send the request.
-    pub async fn send(self) -> Result<TopicDetail, ApiError> {
-        assert!(self.topic_id.is_some(), "topic_id is a required param");
+    fn url(&self) -> Result<String, ApiError> {
+        let Some(topic_id) = self.topic_id else {
+            return Err(ApiError::MissingParameter("topic_id"));
+        };
+
+        let query = serde_qs::to_string(self)?;
+        let url = self
+            .client
+            .api_url(&FORUM_TID.replace("{TOPIC_ID}", &topic_id.to_string()));
 
-        let query = serde_qs::to_string(&self)?;
-        let url = FORUM_TID.replace("{TOPIC_ID}", &self.topic_id.unwrap().to_string());
+        Ok(format!("{url}?{query}"))
+    }
 
-        let url = format!("{url}?{query}");
+    /// Send the request.
+    pub async fn send(self) -> Result<TopicDetail, ApiError> {
+        let url = self.url()?;
         self.client.api_request().get(url, false).await
     }
 
@@ -147,6 +159,29 @@ impl<'a> ForumApiGetTopicDetail<'a> {
     pub fn send_blocking(self) -> Result<TopicDetail, ApiError> {
         crate::RUNTIME.block_on(self.send())
     }
+
+    /// Stream every item of the list, lazily fetching the next page when the current one runs out.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Post, ApiError>> + 'a {
+        let url = self.url();
+        pagination::stream::<TopicDetail>(self.client, url, false)
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    pub async fn collect_all(self, max: Option<usize>) -> Result<Vec<Post>, ApiError> {
+        pagination::collect_all(self.into_stream(), max).await
+    }
+
+    /// Iterate over every item of the list, fetching the next page when the current one runs out.
+    #[cfg(feature = "blocking")]
+    pub fn into_iter_blocking(self) -> impl Iterator<Item = Result<Post, ApiError>> + 'a {
+        pagination::blocking_iter(self.into_stream())
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    #[cfg(feature = "blocking")]
+    pub fn collect_all_blocking(self, max: Option<usize>) -> Result<Vec<Post>, ApiError> {
+        crate::RUNTIME.block_on(self.collect_all(max))
+    }
 }
 
 #[skip_serializing_none]
@@ -155,8 +190,8 @@ pub struct ForumApiGetTopics<'a> {
     #[serde(skip)]
     client: &'a MalClient,
 
-    board_id: Option<u64>,
-    subboard_id: Option<u64>,
+    board_id: Option<BoardId>,
+    subboard_id: Option<BoardId>,
     limit: Option<u8>,
     offset: Option<u64>,
     sort: Option<ForumSort>,
@@ -167,14 +202,14 @@ pub struct ForumApiGetTopics<'a> {
 
 impl<'a> ForumApiGetTopics<'a> {
     /// The board id.
-    pub fn board_id(mut self, id: u64) -> Self {
-        self.board_id = Some(id);
+    pub fn board_id(mut self, id: impl Into<BoardId>) -> Self {
+        self.board_id = Some(id.into());
         self
     }
 
     /// The subboard id.
-    pub fn subboard_id(mut self, id: u64) -> Self {
-        self.subboard_id = Some(id);
+    pub fn subboard_id(mut self, id: impl Into<BoardId>) -> Self {
+        self.subboard_id = Some(id.into());
         self
     }
 
@@ -215,11 +250,15 @@ impl<'a> ForumApiGetTopics<'a> {
         self
     }
 
+    fn url(&self) -> Result<String, ApiError> {
+        let query = serde_qs::to_string(self)?;
+
+        Ok(format!("{}?{query}", self.client.api_url(FORUM_TOPICS)))
+    }
+
     /// Send the request.
     pub async fn send(self) -> Result<ForumTopics, ApiError> {
-        let query = serde_qs::to_string(&self)?;
-        let url = format!("{FORUM_TOPICS}?{query}");
-
+        let url = self.url()?;
         self.client.api_request().get(url, false).await
     }
 
@@ -228,4 +267,27 @@ impl<'a> ForumApiGetTopics<'a> {
     pub fn send_blocking(self) -> Result<ForumTopics, ApiError> {
         crate::RUNTIME.block_on(self.send())
     }
+
+    /// Stream every item of the list, lazily fetching the next page when the current one runs out.
+    pub fn into_stream(self) -> impl Stream<Item = Result<ForumTopic, ApiError>> + 'a {
+        let url = self.url();
+        pagination::stream::<ForumTopics>(self.client, url, false)
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    pub async fn collect_all(self, max: Option<usize>) -> Result<Vec<ForumTopic>, ApiError> {
+        pagination::collect_all(self.into_stream(), max).await
+    }
+
+    /// Iterate over every item of the list, fetching the next page when the current one runs out.
+    #[cfg(feature = "blocking")]
+    pub fn into_iter_blocking(self) -> impl Iterator<Item = Result<ForumTopic, ApiError>> + 'a {
+        pagination::blocking_iter(self.into_stream())
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    #[cfg(feature = "blocking")]
+    pub fn collect_all_blocking(self, max: Option<usize>) -> Result<Vec<ForumTopic>, ApiError> {
+        crate::RUNTIME.block_on(self.collect_all(max))
+    }
 }
@@ -1,17 +1,22 @@
-use const_format::formatcp;
-use itertools::Itertools as _;
+use std::borrow::Borrow;
+
+use futures_util::Stream;
 use serde::Serialize;
 use serde_with::skip_serializing_none;
 
 use crate::{
     api_request::ApiError,
-    objects::{MangaNode, MangaRankingType, MangaSingleList},
-    MalClient, API_URL,
+    fields::{self, MangaField},
+    objects::{
+        MangaId, MangaNode, MangaRankItem, MangaRankingList, MangaRankingType, MangaSingleList,
+        SingleMangaItem,
+    },
+    pagination, MalClient,
 };
 
-pub const MANGA: &str = formatcp!("{API_URL}/manga");
-pub const MANGA_ID: &str = formatcp!("{API_URL}/manga/{{MANGA_ID}}");
-pub const MANGA_RANKING: &str = formatcp!("{API_URL}/manga/ranking");
+pub const MANGA: &str = "/manga";
+pub const MANGA_ID: &str = "/manga/{MANGA_ID}";
+pub const MANGA_RANKING: &str = "/manga/ranking";
 
 #[derive(Debug)]
 pub struct MangaApi<'a> {
@@ -114,10 +119,9 @@ impl<'a> MangaApiGetList<'a> {
         self
     }
 
-    pub fn fields<I: IntoIterator<Item = impl AsRef<str>>>(mut self, fields: I) -> Self {
-        let fields = fields.into_iter().map(|f| f.as_ref().to_string()).join(",");
-
-        self.fields = Some(fields);
+    /// The fields to return. See [MangaField].
+    pub fn fields<I: IntoIterator<Item = impl Borrow<MangaField>>>(mut self, fields: I) -> Self {
+        self.fields = Some(fields::join::<MangaField>(fields));
         self
     }
 
@@ -127,11 +131,15 @@ impl<'a> MangaApiGetList<'a> {
         self
     }
 
+    fn url(&self) -> Result<String, ApiError> {
+        let query = serde_qs::to_string(self)?;
+
+        Ok(format!("{}?{query}", self.client.api_url(MANGA)))
+    }
+
     /// Send the request.
     pub async fn send(self) -> Result<MangaSingleList, ApiError> {
-        let query = serde_qs::to_string(&self)?;
-
-        let url = format!("{MANGA}?{query}");
+        let url = self.url()?;
         self.client.api_request().get(url, false).await
     }
 
@@ -140,6 +148,34 @@ impl<'a> MangaApiGetList<'a> {
     pub fn send_blocking(self) -> Result<MangaSingleList, ApiError> {
         crate::RUNTIME.block_on(self.send())
     }
+
+    /// Stream every item of the list, lazily fetching the next page when the current one runs out.
+    pub fn into_stream(self) -> impl Stream<Item = Result<SingleMangaItem, ApiError>> + 'a {
+        let url = self.url();
+        pagination::stream::<MangaSingleList>(self.client, url, false)
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    pub async fn collect_all(self, max: Option<usize>) -> Result<Vec<SingleMangaItem>, ApiError> {
+        pagination::collect_all(self.into_stream(), max).await
+    }
+
+    /// Iterate over every item of the list, fetching the next page when the current one runs out.
+    #[cfg(feature = "blocking")]
+    pub fn into_iter_blocking(
+        self,
+    ) -> impl Iterator<Item = Result<SingleMangaItem, ApiError>> + 'a {
+        pagination::blocking_iter(self.into_stream())
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    #[cfg(feature = "blocking")]
+    pub fn collect_all_blocking(
+        self,
+        max: Option<usize>,
+    ) -> Result<Vec<SingleMangaItem>, ApiError> {
+        crate::RUNTIME.block_on(self.collect_all(max))
+    }
 }
 
 /// GET manga details.
@@ -151,31 +187,34 @@ pub struct MangaApiGetDetails<'a> {
     #[serde(skip)]
     client: &'a MalClient,
     #[serde(skip)]
-    manga_id: Option<u64>,
+    manga_id: Option<MangaId>,
 
     fields: Option<String>,
 }
 
 impl<'a> MangaApiGetDetails<'a> {
     /// The manga id. This parameter is required.
-    pub fn manga_id(mut self, id: u64) -> Self {
-        self.manga_id = Some(id);
+    pub fn manga_id(mut self, id: impl Into<MangaId>) -> Self {
+        self.manga_id = Some(id.into());
         self
     }
 
-    pub fn fields<I: IntoIterator<Item = impl AsRef<str>>>(mut self, fields: I) -> Self {
-        let fields = fields.into_iter().map(|f| f.as_ref().to_string()).join(",");
-
-        self.fields = Some(fields);
+    /// The fields to return. See [MangaField].
+    pub fn fields<I: IntoIterator<Item = impl Borrow<MangaField>>>(mut self, fields: I) -> Self {
+        self.fields = Some(fields::join::<MangaField>(fields));
         self
     }
 
     /// Send the request.
     pub async fn send(self) -> Result<MangaNode, ApiError> {
-        assert!(self.manga_id.is_some(), "manga_id is a required param");
+        let Some(manga_id) = self.manga_id else {
+            return Err(ApiError::MissingParameter("manga_id"));
+        };
 
         let query = serde_qs::to_string(&self)?;
-        let url = MANGA_ID.replace("{MANGA_ID}", &self.manga_id.unwrap().to_string());
+        let url = self
+            .client
+            .api_url(&MANGA_ID.replace("{MANGA_ID}", &manga_id.to_string()));
 
         let url = format!("{url}?{query}");
         self.client.api_request().get(url, false).await
@@ -223,29 +262,54 @@ impl<'a> MangaApiGetRanking<'a> {
         self
     }
 
-    pub fn fields<I: IntoIterator<Item = impl AsRef<str>>>(mut self, fields: I) -> Self {
-        let fields = fields.into_iter().map(|f| f.as_ref().to_string()).join(",");
-
-        self.fields = Some(fields);
+    /// The fields to return. See [MangaField].
+    pub fn fields<I: IntoIterator<Item = impl Borrow<MangaField>>>(mut self, fields: I) -> Self {
+        self.fields = Some(fields::join::<MangaField>(fields));
         self
     }
 
-    /// Send the request.
-    pub async fn send(self) -> Result<(), ApiError> {
-        assert!(
-            self.ranking_type.is_some(),
-            "ranking_type is a required param"
-        );
+    fn url(&self) -> Result<String, ApiError> {
+        if self.ranking_type.is_none() {
+            return Err(ApiError::MissingParameter("ranking_type"));
+        }
 
-        let query = serde_qs::to_string(&self)?;
-        let url = format!("{MANGA_RANKING}?{query}");
+        let query = serde_qs::to_string(self)?;
 
+        Ok(format!("{}?{query}", self.client.api_url(MANGA_RANKING)))
+    }
+
+    /// Send the request.
+    pub async fn send(self) -> Result<MangaRankingList, ApiError> {
+        let url = self.url()?;
         self.client.api_request().get(url, false).await
     }
 
     /// Send the request.
     #[cfg(feature = "blocking")]
-    pub fn send_blocking(self) -> Result<(), ApiError> {
+    pub fn send_blocking(self) -> Result<MangaRankingList, ApiError> {
         crate::RUNTIME.block_on(self.send())
     }
+
+    /// Stream every item of the list, lazily fetching the next page when the current one runs out.
+    pub fn into_stream(self) -> impl Stream<Item = Result<MangaRankItem, ApiError>> + 'a {
+        let url = self.url();
+        pagination::stream::<MangaRankingList>(self.client, url, false)
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    pub async fn collect_all(self, max: Option<usize>) -> Result<Vec<MangaRankItem>, ApiError> {
+        pagination::collect_all(self.into_stream(), max).await
+    }
+
+    /// Iterate over every item of the list, fetching the next page when the current one runs out.
+    #[cfg(feature = "blocking")]
+    pub fn into_iter_blocking(self) -> impl Iterator<Item = Result<MangaRankItem, ApiError>> + 'a {
+        pagination::blocking_iter(self.into_stream())
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    #[cfg(feature = "blocking")]
+    pub fn collect_all_blocking(self, max: Option<usize>) -> Result<Vec<MangaRankItem>, ApiError> {
+        crate::RUNTIME.block_on(self.collect_all(max))
+    }
 }
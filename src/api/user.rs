@@ -1,15 +1,16 @@
-use const_format::formatcp;
-use itertools::Itertools as _;
+use std::borrow::Borrow;
+
 use serde::Serialize;
 use serde_with::skip_serializing_none;
 
 use crate::{
     api_request::ApiError,
+    fields::{self, UserField},
     objects::{User, Username},
-    MalClient, API_URL,
+    MalClient,
 };
 
-const USER_URL: &str = formatcp!("{API_URL}/users/{{USER_NAME}}");
+const USER_URL: &str = "/users/{USER_NAME}";
 
 #[derive(Debug, Clone)]
 pub struct UserApi<'a> {
@@ -60,16 +61,17 @@ pub struct UserInformationGet<'a> {
 }
 
 impl<'a> UserInformationGet<'a> {
-    pub fn fields<I: IntoIterator<Item = impl AsRef<str>>>(mut self, fields: I) -> Self {
-        let fields = fields.into_iter().map(|f| f.as_ref().to_string()).join(",");
-
-        self.fields = Some(fields);
+    /// The fields to return. See [UserField].
+    pub fn fields<I: IntoIterator<Item = impl Borrow<UserField>>>(mut self, fields: I) -> Self {
+        self.fields = Some(fields::join::<UserField>(fields));
         self
     }
 
     /// Send the request.
     pub async fn send(self) -> Result<User, ApiError> {
-        let url = USER_URL.replace("{USER_NAME}", &Username::Me.to_string());
+        let url = self
+            .client
+            .api_url(&USER_URL.replace("{USER_NAME}", &Username::Me.to_string()));
         let query = serde_qs::to_string(&self)?;
         let url = format!("{url}?{query}");
 
@@ -1,16 +1,18 @@
-use const_format::formatcp;
-use itertools::Itertools as _;
+use std::borrow::Borrow;
+
+use futures_util::Stream;
 use serde::Serialize;
 use serde_with::skip_serializing_none;
 
 use crate::{
     api_request::ApiError,
-    objects::{AnimeList, AnimeListItem, AnimeSort, Username, WatchStatus},
-    MalClient, API_URL,
+    fields::{self, AnimeField},
+    objects::{AnimeId, AnimeItem, AnimeList, AnimeListItem, AnimeSort, Username, WatchStatus},
+    pagination, MalClient,
 };
 
-pub const USER_ANIMELIST_URL: &str = formatcp!("{API_URL}/users/{{USER_NAME}}/animelist");
-pub const USER_ANIME_ID: &str = formatcp!("{API_URL}/anime/{{ANIME_ID}}/my_list_status");
+pub const USER_ANIMELIST_URL: &str = "/users/{USER_NAME}/animelist";
+pub const USER_ANIME_ID: &str = "/anime/{ANIME_ID}/my_list_status";
 
 #[derive(Debug, Clone)]
 pub struct UserAnimeListApi<'a> {
@@ -93,7 +95,7 @@ pub struct UserAnimeListApiPut<'a> {
     #[serde(skip)]
     client: &'a MalClient,
     #[serde(skip)]
-    anime_id: Option<u64>,
+    anime_id: Option<AnimeId>,
 
     status: Option<WatchStatus>,
     is_rewatching: Option<bool>,
@@ -108,8 +110,8 @@ pub struct UserAnimeListApiPut<'a> {
 
 impl<'a> UserAnimeListApiPut<'a> {
     /// The anime id to update. This parameter is required.
-    pub fn anime_id(mut self, id: u64) -> Self {
-        self.anime_id = Some(id);
+    pub fn anime_id(mut self, id: impl Into<AnimeId>) -> Self {
+        self.anime_id = Some(id.into());
         self
     }
 
@@ -160,9 +162,13 @@ impl<'a> UserAnimeListApiPut<'a> {
 
     /// Send the request.
     pub async fn send(self) -> Result<AnimeListItem, ApiError> {
-        assert!(self.anime_id.is_some(), "anime_id is a required param");
+        let Some(anime_id) = self.anime_id else {
+            return Err(ApiError::MissingParameter("anime_id"));
+        };
 
-        let url = USER_ANIME_ID.replace("{ANIME_ID}", &self.anime_id.unwrap().to_string());
+        let url = self
+            .client
+            .api_url(&USER_ANIME_ID.replace("{ANIME_ID}", &anime_id.to_string()));
         self.client.api_request().put(url, Some(&self), true).await
     }
 
@@ -183,21 +189,25 @@ impl<'a> UserAnimeListApiPut<'a> {
 #[derive(Debug)]
 pub struct UserAnimeListApiDelete<'a> {
     client: &'a MalClient,
-    anime_id: Option<u64>,
+    anime_id: Option<AnimeId>,
 }
 
 impl<'a> UserAnimeListApiDelete<'a> {
     /// The anime id in the list to delete. This parameter is required.
-    pub fn anime_id(mut self, id: u64) -> Self {
-        self.anime_id = Some(id);
+    pub fn anime_id(mut self, id: impl Into<AnimeId>) -> Self {
+        self.anime_id = Some(id.into());
         self
     }
 
     /// Send the request.
     pub async fn send(self) -> Result<(), ApiError> {
-        assert!(self.anime_id.is_some(), "anime_id is a required param");
+        let Some(anime_id) = self.anime_id else {
+            return Err(ApiError::MissingParameter("anime_id"));
+        };
 
-        let url = USER_ANIME_ID.replace("{ANIME_ID}", &self.anime_id.unwrap().to_string());
+        let url = self
+            .client
+            .api_url(&USER_ANIME_ID.replace("{ANIME_ID}", &anime_id.to_string()));
         self.client.api_request().delete(url, true).await
     }
 
@@ -259,10 +269,9 @@ impl<'a> UserAnimeListApiGet<'a> {
         self
     }
 
-    pub fn fields<I: IntoIterator<Item = impl AsRef<str>>>(mut self, fields: I) -> Self {
-        let fields = fields.into_iter().map(|f| f.as_ref().to_string()).join(",");
-
-        self.fields = Some(fields);
+    /// The fields to return. See [AnimeField].
+    pub fn fields<I: IntoIterator<Item = impl Borrow<AnimeField>>>(mut self, fields: I) -> Self {
+        self.fields = Some(fields::join::<AnimeField>(fields));
         self
     }
 
@@ -272,21 +281,30 @@ impl<'a> UserAnimeListApiGet<'a> {
         self
     }
 
-    /// Send the request.
-    pub async fn send(self) -> Result<AnimeList, ApiError> {
-        assert!(self.user_name.is_some(), "user_name is a required param");
+    fn url(&self) -> Result<String, ApiError> {
+        let Some(user_name) = &self.user_name else {
+            return Err(ApiError::MissingParameter("user_name"));
+        };
 
-        let username = self.user_name.as_ref().unwrap().to_string();
+        let username = user_name.to_string();
 
-        let query = serde_qs::to_string(&self)?;
-        let url = USER_ANIMELIST_URL.replace("{USER_NAME}", &username);
+        let query = serde_qs::to_string(self)?;
+        let url = self
+            .client
+            .api_url(&USER_ANIMELIST_URL.replace("{USER_NAME}", &username));
 
-        let url = format!("{url}?{query}");
+        Ok(format!("{url}?{query}"))
+    }
 
-        // use access token when Me, and client token when other users
-        let is_auth = matches!(self.user_name.as_ref().unwrap(), Username::Me);
+    /// use access token when Me, and client token when other users
+    fn is_auth(&self) -> bool {
+        matches!(self.user_name, Some(Username::Me))
+    }
 
-        self.client.api_request().get(url, is_auth).await
+    /// Send the request.
+    pub async fn send(self) -> Result<AnimeList, ApiError> {
+        let url = self.url()?;
+        self.client.api_request().get(url, self.is_auth()).await
     }
 
     /// Send the request.
@@ -294,4 +312,27 @@ impl<'a> UserAnimeListApiGet<'a> {
     pub fn send_blocking(self) -> Result<AnimeList, ApiError> {
         crate::RUNTIME.block_on(self.send())
     }
+
+    /// Stream every item of the list, lazily fetching the next page when the current one runs out.
+    pub fn into_stream(self) -> impl Stream<Item = Result<AnimeItem, ApiError>> + 'a {
+        let url = self.url();
+        pagination::stream::<AnimeList>(self.client, url, self.is_auth())
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    pub async fn collect_all(self, max: Option<usize>) -> Result<Vec<AnimeItem>, ApiError> {
+        pagination::collect_all(self.into_stream(), max).await
+    }
+
+    /// Iterate over every item of the list, fetching the next page when the current one runs out.
+    #[cfg(feature = "blocking")]
+    pub fn into_iter_blocking(self) -> impl Iterator<Item = Result<AnimeItem, ApiError>> + 'a {
+        pagination::blocking_iter(self.into_stream())
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    #[cfg(feature = "blocking")]
+    pub fn collect_all_blocking(self, max: Option<usize>) -> Result<Vec<AnimeItem>, ApiError> {
+        crate::RUNTIME.block_on(self.collect_all(max))
+    }
 }
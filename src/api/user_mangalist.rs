@@ -1,16 +1,18 @@
-use const_format::formatcp;
-use itertools::Itertools as _;
+use std::borrow::Borrow;
+
+use futures_util::Stream;
 use serde::Serialize;
 use serde_with::skip_serializing_none;
 
 use crate::{
     api_request::ApiError,
-    objects::{MangaList, MangaListItem, MangaSort, ReadStatus, Username},
-    MalClient, API_URL,
+    fields::{self, MangaField},
+    objects::{MangaId, MangaItem, MangaList, MangaListItem, MangaSort, ReadStatus, Username},
+    pagination, MalClient,
 };
 
-pub const USER_MANGALIST_URL: &str = formatcp!("{API_URL}/users/{{USER_NAME}}/mangalist");
-pub const USER_MANGA_ID: &str = formatcp!("{API_URL}/manga/{{MANGA_ID}}/my_list_status");
+pub const USER_MANGALIST_URL: &str = "/users/{USER_NAME}/mangalist";
+pub const USER_MANGA_ID: &str = "/manga/{MANGA_ID}/my_list_status";
 
 #[derive(Debug, Clone)]
 pub struct UserMangaListApi<'a> {
@@ -91,7 +93,7 @@ pub struct UserMangaListApiPut<'a> {
     #[serde(skip)]
     client: &'a MalClient,
     #[serde(skip)]
-    manga_id: Option<u64>,
+    manga_id: Option<MangaId>,
 
     status: Option<ReadStatus>,
     is_rereading: Option<bool>,
@@ -107,8 +109,8 @@ pub struct UserMangaListApiPut<'a> {
 
 impl<'a> UserMangaListApiPut<'a> {
     /// The manga id to update. This parameter is required.
-    pub fn manga_id(mut self, id: u64) -> Self {
-        self.manga_id = Some(id);
+    pub fn manga_id(mut self, id: impl Into<MangaId>) -> Self {
+        self.manga_id = Some(id.into());
         self
     }
 
@@ -167,9 +169,13 @@ impl<'a> UserMangaListApiPut<'a> {
 
     /// Send the request.
     pub async fn send(self) -> Result<MangaListItem, ApiError> {
-        assert!(self.manga_id.is_some(), "manga_id is a required param");
+        let Some(manga_id) = self.manga_id else {
+            return Err(ApiError::MissingParameter("manga_id"));
+        };
 
-        let url = USER_MANGA_ID.replace("{MANGA_ID}", &self.manga_id.unwrap().to_string());
+        let url = self
+            .client
+            .api_url(&USER_MANGA_ID.replace("{MANGA_ID}", &manga_id.to_string()));
         self.client.api_request().put(url, Some(&self), true).await
     }
 
@@ -189,21 +195,25 @@ impl<'a> UserMangaListApiPut<'a> {
 #[derive(Debug)]
 pub struct UserMangaListApiDelete<'a> {
     client: &'a MalClient,
-    manga_id: Option<u64>,
+    manga_id: Option<MangaId>,
 }
 
 impl<'a> UserMangaListApiDelete<'a> {
     /// The manga id to delete. This parameter is required.
-    pub fn manga_id(mut self, id: u64) -> Self {
-        self.manga_id = Some(id);
+    pub fn manga_id(mut self, id: impl Into<MangaId>) -> Self {
+        self.manga_id = Some(id.into());
         self
     }
 
     /// Send the request.
     pub async fn send(self) -> Result<(), ApiError> {
-        assert!(self.manga_id.is_some(), "manga_id is a required param");
+        let Some(manga_id) = self.manga_id else {
+            return Err(ApiError::MissingParameter("manga_id"));
+        };
 
-        let url = USER_MANGA_ID.replace("{MANGA_ID}", &self.manga_id.unwrap().to_string());
+        let url = self
+            .client
+            .api_url(&USER_MANGA_ID.replace("{MANGA_ID}", &manga_id.to_string()));
         self.client.api_request().delete(url, true).await
     }
 
@@ -266,10 +276,9 @@ impl<'a> UserMangaListApiGet<'a> {
         self
     }
 
-    pub fn fields<I: IntoIterator<Item = impl AsRef<str>>>(mut self, fields: I) -> Self {
-        let fields = fields.into_iter().map(|f| f.as_ref().to_string()).join(",");
-
-        self.fields = Some(fields);
+    /// The fields to return. See [MangaField].
+    pub fn fields<I: IntoIterator<Item = impl Borrow<MangaField>>>(mut self, fields: I) -> Self {
+        self.fields = Some(fields::join::<MangaField>(fields));
         self
     }
 
@@ -279,21 +288,30 @@ impl<'a> UserMangaListApiGet<'a> {
         self
     }
 
-    /// Send the request.
-    pub async fn send(self) -> Result<MangaList, ApiError> {
-        assert!(self.user_name.is_some(), "user_name is a required param");
+    fn url(&self) -> Result<String, ApiError> {
+        let Some(user_name) = &self.user_name else {
+            return Err(ApiError::MissingParameter("user_name"));
+        };
 
-        let username = self.user_name.as_ref().unwrap().to_string();
+        let username = user_name.to_string();
 
-        let query = serde_qs::to_string(&self)?;
-        let url = USER_MANGALIST_URL.replace("{USER_NAME}", &username);
+        let query = serde_qs::to_string(self)?;
+        let url = self
+            .client
+            .api_url(&USER_MANGALIST_URL.replace("{USER_NAME}", &username));
 
-        let url = format!("{url}?{query}");
+        Ok(format!("{url}?{query}"))
+    }
 
-        // use access token when Me, and client token when other users
-        let is_auth = matches!(self.user_name.as_ref().unwrap(), Username::Me);
+    /// use access token when Me, and client token when other users
+    fn is_auth(&self) -> bool {
+        matches!(self.user_name, Some(Username::Me))
+    }
 
-        self.client.api_request().get(url, is_auth).await
+    /// Send the request.
+    pub async fn send(self) -> Result<MangaList, ApiError> {
+        let url = self.url()?;
+        self.client.api_request().get(url, self.is_auth()).await
     }
 
     /// Send the request.
@@ -301,4 +319,27 @@ impl<'a> UserMangaListApiGet<'a> {
     pub fn send_blocking(self) -> Result<MangaList, ApiError> {
         crate::RUNTIME.block_on(self.send())
     }
+
+    /// Stream every item of the list, lazily fetching the next page when the current one runs out.
+    pub fn into_stream(self) -> impl Stream<Item = Result<MangaItem, ApiError>> + 'a {
+        let url = self.url();
+        pagination::stream::<MangaList>(self.client, url, self.is_auth())
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    pub async fn collect_all(self, max: Option<usize>) -> Result<Vec<MangaItem>, ApiError> {
+        pagination::collect_all(self.into_stream(), max).await
+    }
+
+    /// Iterate over every item of the list, fetching the next page when the current one runs out.
+    #[cfg(feature = "blocking")]
+    pub fn into_iter_blocking(self) -> impl Iterator<Item = Result<MangaItem, ApiError>> + 'a {
+        pagination::blocking_iter(self.into_stream())
+    }
+
+    /// Fetch every page and collect the items, stopping once `max` items were received.
+    #[cfg(feature = "blocking")]
+    pub fn collect_all_blocking(self, max: Option<usize>) -> Result<Vec<MangaItem>, ApiError> {
+        crate::RUNTIME.block_on(self.collect_all(max))
+    }
 }
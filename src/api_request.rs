@@ -1,10 +1,38 @@
-use reqwest::{Error, IntoUrl, StatusCode};
-use serde::de::DeserializeOwned;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::{
+    future::{BoxFuture, WeakShared},
+    FutureExt as _,
+};
+use oauth2::AccessToken;
+use reqwest::{
+    header::{
+        HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue, AUTHORIZATION, CONTENT_TYPE,
+        RETRY_AFTER, WWW_AUTHENTICATE,
+    },
+    IntoUrl, Method, StatusCode, Url,
+};
+use serde::de::{DeserializeOwned, IgnoredAny};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::debug;
+use tower::ServiceExt;
+use tracing::{debug, warn};
 
-use crate::MalClient;
+use crate::{
+    auth::TokenError,
+    cache::{CacheKey, CachedResponse, Endpoint},
+    cassette::{CassetteError, CassetteMode},
+    rate_limit::{AuthMode, RateLimiter},
+    retry,
+    service::MalRequest,
+    transport::{HttpRequest, HttpResponse, TransportError},
+    MalClient,
+};
 
 #[derive(Copy, Clone, Debug)]
 pub(crate) enum RequestMethod {
@@ -13,44 +41,191 @@ pub(crate) enum RequestMethod {
     Delete,
 }
 
+impl RequestMethod {
+    /// Whether the request can safely be sent again. Only GETs are; PUT/DELETE modify the user's list.
+    fn is_idempotent(self) -> bool {
+        matches!(self, Self::Get)
+    }
+}
+
+impl From<RequestMethod> for Method {
+    fn from(method: RequestMethod) -> Self {
+        match method {
+            RequestMethod::Get => Method::GET,
+            RequestMethod::Put => Method::PUT,
+            RequestMethod::Delete => Method::DELETE,
+        }
+    }
+}
+
+/// The error of a request to mal.
+///
+/// When [identical GETs are deduplicated](crate::MalClientBuilder::deduplicate_requests), the callers which
+/// joined a request that couldn't be sent (e.g. the connection failed, or the access token couldn't be
+/// refreshed) get its error as [ApiError::Shared]; error responses from mal are still parsed by each caller.
+/// [ApiError::status], [ApiError::response], [ApiError::is_retryable] and [ApiError::is_auth_error] look
+/// through it; match on the wrapped error to tell the other variants apart.
 #[derive(Error, Debug)]
 pub enum ApiError {
-    #[error("Error occurred during request {0:?}")]
-    ReqwestError(String),
-    #[error("Invalid token (Expired access tokens, Invalid access tokens, etc.)")]
-    InvalidToken,
-    #[error("Invalid Parameters")]
-    InvalidParameters,
-    #[error("Access is forbidden (DoS detected etc.)")]
-    Forbidden,
-    #[error("Not found")]
-    NotFound,
-    #[error("Status code : {0:?}")]
-    StatusCode(StatusCode),
+    #[error("Error occurred during request: {0}")]
+    ReqwestError(#[from] reqwest::Error),
+    #[error("Invalid token (Expired access tokens, Invalid access tokens, etc.): {0}")]
+    InvalidToken(Box<ErrorResponse>),
+    #[error("Invalid Parameters: {0}")]
+    InvalidParameters(Box<ErrorResponse>),
+    #[error("Access is forbidden (DoS detected etc.): {0}")]
+    Forbidden(Box<ErrorResponse>),
+    #[error("Not found: {0}")]
+    NotFound(Box<ErrorResponse>),
+    /// Any other error status, without an error body from mal.
+    #[error("{0}")]
+    StatusCode(Box<ErrorResponse>),
     #[error("{0}")]
     ParseError(#[from] serde_json::Error),
     #[error("access token missing")]
     AccessTokenError,
-    #[error("{status} - {error}: {message}")]
-    ErrorMessage {
-        status: StatusCode,
-        error: String,
-        message: String,
-    },
+    #[error("missing required parameter '{0}'")]
+    MissingParameter(&'static str),
+    /// The url of the next page isn't on the api root.
+    #[error("next page url is not on the api root: {0}")]
+    InvalidNextPage(String),
+    /// Any other status (even a 200) with an error body from mal.
+    #[error("{0}")]
+    ErrorMessage(Box<ErrorResponse>),
     #[error("{0}")]
     QuerySerError(#[from] serde_qs::Error),
+    #[error("failed to refresh access token: {0}")]
+    TokenError(#[from] TokenError),
+    #[error("cassette failed: {0}")]
+    Cassette(#[from] CassetteError),
+    #[error("failed to send request: {0}")]
+    Transport(#[from] TransportError),
+    #[error("invalid header value: {0}")]
+    InvalidHeader(#[from] InvalidHeaderValue),
+    /// The error of a GET which was shared with identical GETs sent at the same time.
+    ///
+    /// Every caller of a GET which was joined gets its error wrapped in this; a GET nobody joined returns
+    /// its error as is.
+    #[error("{0}")]
+    Shared(Arc<ApiError>),
 }
 
-impl From<reqwest::Error> for ApiError {
-    fn from(e: Error) -> Self {
-        Self::ReqwestError(format!("{:?}", e))
+impl ApiError {
+    /// The error response mal sent, if the request got that far.
+    pub fn response(&self) -> Option<&ErrorResponse> {
+        match self {
+            Self::InvalidToken(response)
+            | Self::InvalidParameters(response)
+            | Self::Forbidden(response)
+            | Self::NotFound(response)
+            | Self::StatusCode(response)
+            | Self::ErrorMessage(response) => Some(response),
+            Self::Shared(e) => e.response(),
+            _ => None,
+        }
+    }
+
+    /// The status of mal's response, if the request got that far.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::ReqwestError(e) => e.status(),
+            Self::Shared(e) => e.status(),
+            _ => self.response().map(|response| response.status),
+        }
+    }
+
+    /// Whether sending the request again may succeed.
+    ///
+    /// This is the case for connection failures, timeouts, `429 Too Many Requests` and `5xx` responses.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::ReqwestError(e) if e.is_connect() || e.is_timeout() || e.is_request() => {
+                return true;
+            }
+            Self::Transport(e) if e.is_connection_error() => return true,
+            Self::Shared(e) => return e.is_retryable(),
+            _ => (),
+        }
+
+        self.status().is_some_and(|status| {
+            status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+        })
+    }
+
+    /// Whether the access token is the problem: it was rejected, is missing, or couldn't be refreshed.
+    ///
+    /// The user probably needs to authenticate again.
+    pub fn is_auth_error(&self) -> bool {
+        match self {
+            Self::InvalidToken(_) | Self::AccessTokenError | Self::TokenError(_) => true,
+            Self::Shared(e) => e.is_auth_error(),
+            _ => false,
+        }
     }
 }
 
+/// An error response from mal, and the request it answered.
+#[derive(Debug, Clone)]
+pub struct ErrorResponse {
+    pub method: Method,
+    pub url: Url,
+    pub status: StatusCode,
+    /// The `error` of mal's json error body, if it sent one.
+    pub error: Option<String>,
+    /// The `message` of mal's json error body, if it sent one.
+    pub message: Option<String>,
+    /// The response headers that help handling the error: `Retry-After`, `WWW-Authenticate` and `Content-Type`.
+    pub headers: HeaderMap,
+    /// The raw response body.
+    pub body: String,
+}
+
+impl ErrorResponse {
+    /// How long mal asked to wait before sending the request again.
+    pub fn retry_after(&self) -> Option<Duration> {
+        retry::retry_after(&self.headers)
+    }
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} - {}", self.method, self.url, self.status)?;
+
+        if let Some(error) = &self.error {
+            write!(f, " - {error}")?;
+        }
+
+        match &self.message {
+            Some(message) if !message.is_empty() => write!(f, ": {message}"),
+            _ => Ok(()),
+        }
+    }
+}
+
+// the headers kept in an ErrorResponse
+const ERROR_HEADERS: [HeaderName; 3] = [RETRY_AFTER, WWW_AUTHENTICATE, CONTENT_TYPE];
+
 #[derive(Debug, Clone, Deserialize)]
 struct ApiRequestError {
     error: String,
-    message: String,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// GETs currently in flight, by url and the access token they are sent with (`None` for the client id).
+///
+/// Keying by the token, not just whether one is used, lets clients of different users share the map.
+pub(crate) type InFlight = Arc<Mutex<HashMap<(String, Option<String>), WeakShared<Flight>>>>;
+
+type Flight = BoxFuture<'static, Result<Arc<RawResponse>, Arc<ApiError>>>;
+
+/// A response which wasn't parsed yet.
+pub(crate) struct RawResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: String,
+    // the access token the request was sent with
+    access_token: Option<AccessToken>,
 }
 
 #[derive(Clone, Debug)]
@@ -71,12 +246,17 @@ impl<'a> ApiRequest<'a> {
             .await
     }
 
-    pub(crate) async fn delete<D>(&self, url: impl IntoUrl, is_auth: bool) -> Result<D, ApiError>
-    where
-        D: DeserializeOwned,
-    {
-        self.api_request(url.into_url()?, RequestMethod::Delete, None::<()>, is_auth)
-            .await
+    pub(crate) async fn delete(&self, url: impl IntoUrl, is_auth: bool) -> Result<(), ApiError> {
+        // the body of a successful delete isn't documented, so accept any json
+        self.api_request::<IgnoredAny, _>(
+            url.into_url()?,
+            RequestMethod::Delete,
+            None::<()>,
+            is_auth,
+        )
+        .await?;
+
+        Ok(())
     }
 
     pub(crate) async fn put<D, P: Serialize>(
@@ -104,76 +284,509 @@ impl<'a> ApiRequest<'a> {
     where
         D: DeserializeOwned,
     {
-        let mut request = match method {
-            RequestMethod::Get => self.client.http.get(url.into_url()?),
-            RequestMethod::Delete => self.client.http.delete(url.into_url()?),
-            RequestMethod::Put => self.client.http.put(url.into_url()?),
-        };
+        let url = url.into_url()?;
 
-        if matches!(method, RequestMethod::Put) {
-            if let Some(data) = &data {
-                request = request.form(data);
+        if is_auth {
+            let tokens = self.client.tokens();
+            // the token is likely still valid, so the request only fails if mal rejects it.
+            // replayed requests don't go to mal, so their token doesn't need to be valid
+            if tokens.expires_soon() && !self.replaying() {
+                if let Err(e) = self.client.refresh_tokens(&tokens.access_token).await {
+                    warn!(error = %e, "refreshing the access token before it expires failed");
+                }
             }
         }
 
-        let request = if is_auth {
-            request.bearer_auth(self.client.auth_tokens.access_token.secret())
-        } else {
-            request.header("X-MAL-CLIENT-ID", &*self.client.client_id)
+        // only GETs are cached
+        let endpoint = matches!(method, RequestMethod::Get)
+            .then(|| Endpoint::from_path(self.client.api_path(&url)))
+            .flatten();
+
+        if let Some(cached) = self.cached(endpoint, &url, is_auth).await {
+            debug!(%url, "cached mal response");
+
+            let headers = HeaderMap::new();
+            return parse_response(method, &url, StatusCode::OK, &headers, cached.body());
+        }
+
+        let response = match (method, &self.client.in_flight) {
+            (RequestMethod::Get, Some(in_flight)) => {
+                self.send_deduplicated(in_flight, &url, is_auth).await?
+            }
+            _ => Arc::new(
+                self.send_request(&url, method, data.as_ref(), is_auth)
+                    .await?,
+            ),
         };
 
-        let response = request.send().await?;
+        let RawResponse {
+            status,
+            headers,
+            body,
+            access_token,
+        } = &*response;
+
+        let result = parse_response(method, &url, *status, headers, body);
+        if result.is_ok() {
+            self.update_cache(method, endpoint, &url, access_token.as_ref(), body)
+                .await;
+        }
+
+        result
+    }
+
+    /// Join the identical GET already in flight, or send it and let later identical GETs join it.
+    ///
+    /// Every caller deserializes the shared response itself. If the request fails, callers which
+    /// shared it get the error as [ApiError::Shared]; a caller which didn't gets the error itself.
+    async fn send_deduplicated(
+        &self,
+        in_flight: &InFlight,
+        url: &Url,
+        is_auth: bool,
+    ) -> Result<Arc<RawResponse>, ApiError> {
+        let access_token = is_auth.then(|| self.client.tokens().access_token.secret().clone());
+        let key = (url.to_string(), access_token);
+
+        let flight = {
+            let mut in_flight_map = in_flight.lock().unwrap();
+
+            match in_flight_map.get(&key).and_then(WeakShared::upgrade) {
+                Some(flight) => flight,
+                None => {
+                    // the future must not borrow the caller, since whichever caller polls it drives it
+                    let client = self.client.clone();
+                    let url = url.clone();
+                    let in_flight = in_flight.clone();
+                    let flight_key = key.clone();
+
+                    let flight = async move {
+                        let result = client
+                            .api_request()
+                            .send_request(&url, RequestMethod::Get, None::<&()>, is_auth)
+                            .await;
 
-        let status = response.status();
-        let text = response.text().await?;
+                        // later requests must send again instead of joining a finished flight
+                        in_flight.lock().unwrap().remove(&flight_key);
 
-        debug!(status = status.as_u16(), response = text, "mal reponse");
+                        result.map(Arc::new).map_err(Arc::new)
+                    }
+                    .boxed()
+                    .shared();
 
-        let error = serde_json::from_str::<ApiRequestError>(&text);
+                    // flights nobody waits for anymore were dropped, forget them
+                    in_flight_map.retain(|_, flight| flight.upgrade().is_some());
 
-        match status {
-            StatusCode::BAD_REQUEST => {
-                return Err(ApiError::InvalidParameters);
+                    if let Some(weak) = flight.downgrade() {
+                        in_flight_map.insert(key, weak);
+                    }
+
+                    flight
+                }
             }
+        };
+
+        flight
+            .await
+            .map_err(|e| Arc::try_unwrap(e).unwrap_or_else(ApiError::Shared))
+    }
 
-            StatusCode::UNAUTHORIZED => {
-                return Err(ApiError::InvalidToken);
+    /// Send the request, retrying it and refreshing the access token as needed.
+    async fn send_request<P: Serialize>(
+        &self,
+        url: &Url,
+        method: RequestMethod,
+        data: Option<&P>,
+        is_auth: bool,
+    ) -> Result<RawResponse, ApiError> {
+        let retry_policy = &self.client.retry_policy;
+        let mut attempt = 1;
+        let mut refreshed = false;
+
+        loop {
+            if let Some(rate_limiter) = self.rate_limiter() {
+                rate_limiter.acquire(AuthMode::new(is_auth)).await;
             }
 
-            StatusCode::FORBIDDEN => {
-                return Err(ApiError::Forbidden);
+            let access_token = is_auth.then(|| self.client.tokens().access_token);
+
+            let mut headers = HeaderMap::new();
+            if let Some(access_token) = &access_token {
+                let value = format!("Bearer {}", access_token.secret());
+                headers.insert(AUTHORIZATION, HeaderValue::from_str(&value)?);
+            } else {
+                let value = HeaderValue::from_str(&self.client.client_id)?;
+                headers.insert("X-MAL-CLIENT-ID", value);
             }
 
-            StatusCode::NOT_FOUND => {
-                return Err(ApiError::NotFound);
+            let mut body = None;
+            if matches!(method, RequestMethod::Put) {
+                if let Some(data) = data {
+                    let form = HeaderValue::from_static("application/x-www-form-urlencoded");
+                    headers.insert(CONTENT_TYPE, form);
+                    body = Some(serde_qs::to_string(data)?.into_bytes());
+                }
             }
 
-            // only one that is allowed to pass
-            StatusCode::OK => (),
+            let request = HttpRequest {
+                method: method.into(),
+                url: url.clone(),
+                headers,
+                body,
+            };
+
+            let request = MalRequest {
+                http: request,
+                auth: AuthMode::new(is_auth),
+                attempt,
+            };
+
+            let response = match self.client.service.clone().oneshot(request).await {
+                Ok(response) => response,
+                Err(ApiError::Transport(e)) => {
+                    let delay = retry_policy.delay_for_error(attempt, method.is_idempotent(), &e);
+                    let Some(delay) = delay else {
+                        return Err(e.into());
+                    };
+
+                    debug!(attempt, ?delay, error = %e, "retrying mal request");
+
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let status = response.status;
 
-            v => {
-                if let Ok(error) = error {
-                    return Err(ApiError::ErrorMessage {
-                        status: v,
-                        error: error.error,
-                        message: error.message,
-                    });
-                } else {
-                    return Err(ApiError::StatusCode(v));
+            // the access token was rejected; refresh it and retry the request once
+            if status == StatusCode::UNAUTHORIZED && !refreshed {
+                if let Some(access_token) = &access_token {
+                    if self.client.refresh_tokens(access_token).await? {
+                        refreshed = true;
+                        continue;
+                    }
                 }
             }
-        }
 
-        if let Ok(error) = error {
-            return Err(ApiError::ErrorMessage {
-                status: StatusCode::OK,
-                error: error.error,
-                message: error.message,
+            let delay = retry_policy.delay_for_status(
+                attempt,
+                method.is_idempotent(),
+                status,
+                &response.headers,
+            );
+
+            if let Some(delay) = delay {
+                debug!(
+                    attempt,
+                    ?delay,
+                    status = status.as_u16(),
+                    "retrying mal request"
+                );
+
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                continue;
+            }
+
+            let HttpResponse { headers, body, .. } = response;
+            let body = String::from_utf8_lossy(&body).into_owned();
+
+            debug!(status = status.as_u16(), response = %body, "mal reponse");
+
+            return Ok(RawResponse {
+                status,
+                headers,
+                body,
+                access_token,
             });
         }
+    }
+
+    /// The cached response to a GET of `url`, if there is one.
+    async fn cached(
+        &self,
+        endpoint: Option<Endpoint>,
+        url: &Url,
+        is_auth: bool,
+    ) -> Option<CachedResponse> {
+        let cache = self.client.cache.as_ref()?;
+        let endpoint = endpoint?;
+
+        // don't bother looking up endpoints which are never cached
+        cache.ttl_for(endpoint)?;
+
+        let access_token = is_auth.then(|| self.client.tokens().access_token);
+        let key = CacheKey::new(endpoint, &Method::GET, url, access_token.as_ref());
+
+        match cache.get(&key).await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!(error = %e, "reading the mal response cache failed");
+                None
+            }
+        }
+    }
+
+    /// Cache the response to a GET, or remove the cached responses a PUT/DELETE changed.
+    async fn update_cache(
+        &self,
+        method: RequestMethod,
+        endpoint: Option<Endpoint>,
+        url: &Url,
+        access_token: Option<&AccessToken>,
+        body: &str,
+    ) {
+        let Some(cache) = &self.client.cache else {
+            return;
+        };
+
+        let result = match (method, endpoint) {
+            (RequestMethod::Get, Some(endpoint)) => {
+                let key = CacheKey::new(endpoint, &Method::GET, url, access_token);
+                cache.put(&key, body).await
+            }
+            (RequestMethod::Get, None) => Ok(()),
+            (RequestMethod::Put | RequestMethod::Delete, _) => {
+                cache
+                    .invalidate_list_status(self.client.api_path(url))
+                    .await
+            }
+        };
+
+        if let Err(e) = result {
+            warn!(error = %e, "updating the mal response cache failed");
+        }
+    }
+
+    /// The rate limiter requests go through. Replayed requests don't go to mal, so they aren't limited.
+    fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.client
+            .rate_limiter
+            .as_ref()
+            .filter(|_| !self.replaying())
+    }
+
+    /// Whether requests are answered from a cassette instead of being sent.
+    fn replaying(&self) -> bool {
+        self.client
+            .cassette
+            .as_ref()
+            .is_some_and(|cassette| cassette.mode() == CassetteMode::Replay)
+    }
+}
+
+fn parse_response<D>(
+    method: RequestMethod,
+    url: &Url,
+    status: StatusCode,
+    headers: &HeaderMap,
+    text: &str,
+) -> Result<D, ApiError>
+where
+    D: DeserializeOwned,
+{
+    let error = serde_json::from_str::<ApiRequestError>(text).ok();
+    let has_error_body = error.is_some();
+
+    let response = || {
+        let (error, message) = error
+            .map(|error| (Some(error.error), error.message))
+            .unwrap_or_default();
+
+        let headers = ERROR_HEADERS
+            .iter()
+            .filter_map(|name| Some((name.clone(), headers.get(name)?.clone())))
+            .collect();
+
+        Box::new(ErrorResponse {
+            method: method.into(),
+            url: url.clone(),
+            status,
+            error,
+            message,
+            headers,
+            body: text.to_owned(),
+        })
+    };
+
+    match status {
+        StatusCode::BAD_REQUEST => {
+            return Err(ApiError::InvalidParameters(response()));
+        }
+
+        StatusCode::UNAUTHORIZED => {
+            return Err(ApiError::InvalidToken(response()));
+        }
+
+        StatusCode::FORBIDDEN => {
+            return Err(ApiError::Forbidden(response()));
+        }
+
+        StatusCode::NOT_FOUND => {
+            return Err(ApiError::NotFound(response()));
+        }
 
-        let data = serde_json::from_str(&text)?;
+        // only one that is allowed to pass
+        StatusCode::OK => (),
 
-        Ok(data)
+        _ => {
+            if has_error_body {
+                return Err(ApiError::ErrorMessage(response()));
+            } else {
+                return Err(ApiError::StatusCode(response()));
+            }
+        }
+    }
+
+    if has_error_body {
+        return Err(ApiError::ErrorMessage(response()));
+    }
+
+    let data = serde_json::from_str(text)?;
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "testing")]
+    mod with_server {
+        use std::{
+            sync::{
+                atomic::{AtomicUsize, Ordering},
+                Arc,
+            },
+            time::Duration,
+        };
+
+        use reqwest::StatusCode;
+
+        use crate::{
+            api_request::ApiError,
+            retry::RetryPolicy,
+            testing::MockServer,
+            transport::{HttpRequest, HttpTransport, TransportError, TransportFuture},
+            MalClient,
+        };
+
+        async fn details(client: &MalClient, anime_id: u64) -> Result<(), ApiError> {
+            client
+                .anime()
+                .get()
+                .details()
+                .anime_id(anime_id)
+                .send()
+                .await
+                .map(drop)
+        }
+
+        #[tokio::test]
+        async fn identical_gets_share_one_request() {
+            let server = MockServer::start().await.unwrap();
+            let client = server.client();
+
+            let (a, b) = tokio::join!(details(&client, 5114), details(&client, 5114));
+            a.unwrap();
+            b.unwrap();
+            assert_eq!(server.request_count(), 1);
+
+            // a finished request isn't joined
+            details(&client, 5114).await.unwrap();
+            assert_eq!(server.request_count(), 2);
+
+            let (a, b) = tokio::join!(details(&client, 5114), details(&client, 9253));
+            a.unwrap();
+            b.unwrap();
+            assert_eq!(server.request_count(), 4);
+        }
+
+        #[tokio::test]
+        async fn identical_gets_are_sent_separately_when_not_deduplicated() {
+            let server = MockServer::start().await.unwrap();
+            let client = server
+                .client_builder()
+                .deduplicate_requests(false)
+                .build()
+                .unwrap();
+
+            let (a, b) = tokio::join!(details(&client, 5114), details(&client, 5114));
+            a.unwrap();
+            b.unwrap();
+            assert_eq!(server.request_count(), 2);
+        }
+
+        /// Fails every request after a moment, so that identical requests can join it.
+        #[derive(Default)]
+        struct FailingTransport {
+            sent: AtomicUsize,
+        }
+
+        impl HttpTransport for FailingTransport {
+            fn send(&self, _request: HttpRequest) -> TransportFuture<'_> {
+                self.sent.fetch_add(1, Ordering::SeqCst);
+
+                Box::pin(async {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    Err(TransportError::Connection("connection reset".into()))
+                })
+            }
+        }
+
+        #[tokio::test]
+        async fn joined_gets_get_the_error_as_shared() {
+            let server = MockServer::start().await.unwrap();
+            let transport = Arc::new(FailingTransport::default());
+            let client = server
+                .client_builder()
+                .retry_policy(RetryPolicy::none())
+                .transport(transport.clone())
+                .build()
+                .unwrap();
+
+            let (a, b) = tokio::join!(details(&client, 5114), details(&client, 5114));
+            let (a, b) = (a.unwrap_err(), b.unwrap_err());
+            assert_eq!(transport.sent.load(Ordering::SeqCst), 1);
+
+            for e in [&a, &b] {
+                let ApiError::Shared(e) = e else {
+                    panic!("not shared: {e:?}");
+                };
+                assert!(matches!(
+                    **e,
+                    ApiError::Transport(TransportError::Connection(_))
+                ));
+            }
+
+            assert!(a.is_retryable() && b.is_retryable());
+
+            // a request nobody joined returns its error as is
+            let e = details(&client, 5114).await.unwrap_err();
+            assert!(matches!(
+                e,
+                ApiError::Transport(TransportError::Connection(_))
+            ));
+        }
+
+        #[tokio::test]
+        async fn error_responses_are_parsed_by_every_caller() {
+            let server = MockServer::start().await.unwrap();
+            let client = server
+                .client_builder()
+                .retry_policy(RetryPolicy::none())
+                .build()
+                .unwrap();
+
+            server.fail_next(StatusCode::SERVICE_UNAVAILABLE, None);
+
+            let (a, b) = tokio::join!(details(&client, 5114), details(&client, 5114));
+            assert_eq!(server.request_count(), 1);
+
+            for e in [a.unwrap_err(), b.unwrap_err()] {
+                assert!(!matches!(e, ApiError::Shared(_)));
+                assert_eq!(e.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+            }
+        }
     }
 }
@@ -1,11 +1,23 @@
-use std::{fmt, time::Duration};
+use std::{
+    borrow::Cow,
+    fmt,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use chrono::Utc;
-use const_format::formatcp;
 use oauth2::{
-    basic::{BasicClient, BasicErrorResponse, BasicErrorResponseType},
+    basic::{BasicClient, BasicErrorResponse, BasicErrorResponseType, BasicTokenResponse},
     reqwest::Client,
-    AccessToken, EndpointNotSet, EndpointSet, PkceCodeVerifier, Scope,
+    AccessToken, EndpointNotSet, EndpointSet, HttpClientError, PkceCodeVerifier, RequestTokenError,
+    Scope,
 };
 use oauth2::{
     AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge, RedirectUrl,
@@ -14,11 +26,16 @@ use oauth2::{
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tokio::{fs, io::AsyncWriteExt as _, sync::watch};
+use tracing::warn;
+
+use crate::{transport::BoxError, BASE_URL};
 
-use crate::BASE_URL;
+const AUTH_PATH: &str = "/oauth2/authorize";
+const TOKEN_PATH: &str = "/oauth2/token";
 
-const AUTH_URL: &str = formatcp!("{BASE_URL}/oauth2/authorize");
-const TOKEN_URL: &str = formatcp!("{BASE_URL}/oauth2/token");
+// how long a pending authorization can be finished by default
+const PENDING_TTL: Duration = Duration::from_secs(10 * 60);
 
 /// Error type for Authorization methods
 #[derive(Error, Debug, PartialEq)]
@@ -46,8 +63,15 @@ pub enum AuthError {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthTokens {
     pub access_token: AccessToken,
+    /// Empty if mal didn't send one. There is then nothing to refresh with, and the user has to
+    /// authenticate again once the access token expires.
     pub refresh_token: RefreshToken,
+    /// When the access token expires, in utc seconds.
+    ///
+    /// 0 means the expiry is unknown, because mal didn't send one: the access token is never refreshed
+    /// ahead of time and is used until mal rejects it, and [Auth::is_access_valid] only checks that there is one.
     pub expires_at: u64,
+    /// When the refresh token expires, in utc seconds. 0 if there is no refresh token.
     pub refresh_expires_at: u64,
 }
 
@@ -63,6 +87,16 @@ impl Default for AuthTokens {
 }
 
 impl AuthTokens {
+    /// Whether the access token should be refreshed before it is used.
+    ///
+    /// An expiry of 0 means it is unknown, in which case we wait for mal to reject the token.
+    pub(crate) fn expires_soon(&self) -> bool {
+        // refresh a little early so the token doesn't expire while a request is in flight
+        const MARGIN: u64 = 60;
+
+        self.expires_at != 0 && Utc::now().timestamp() as u64 + MARGIN >= self.expires_at
+    }
+
     #[deprecated(since = "0.2.0", note = "use Auth::from_auth_tokens()")]
     pub fn to_auth(
         &self,
@@ -110,9 +144,10 @@ pub struct Auth {
     refresh_expires_at: u64,
     scopes: Vec<Scope>,
     async_client: Client,
-    // I'd use PckeCodeVerifier here, but it's !Clone
-    pkce_code_verifier: Option<String>,
-    client_req: Option<ClientAuthRequest>,
+    // the request started by authenticate(), until it is finished
+    pending: Option<PendingAuthorization>,
+    pending_ttl: Duration,
+    token_store: Option<Arc<dyn TokenStore>>,
 }
 
 impl fmt::Debug for Auth {
@@ -150,8 +185,8 @@ impl Auth {
     ) -> Self {
         let client = BasicClient::new(client_id.clone())
             .set_client_secret(client_secret.clone())
-            .set_auth_uri(AuthUrl::new(AUTH_URL.to_owned()).unwrap())
-            .set_token_uri(TokenUrl::new(TOKEN_URL.to_owned()).unwrap())
+            .set_auth_uri(AuthUrl::new(format!("{BASE_URL}{AUTH_PATH}")).unwrap())
+            .set_token_uri(TokenUrl::new(format!("{BASE_URL}{TOKEN_PATH}")).unwrap())
             .set_redirect_uri(redirect_uri);
 
         Self {
@@ -164,8 +199,9 @@ impl Auth {
             refresh_expires_at: 0,
             scopes: Vec::new(),
             async_client: Client::new(),
-            pkce_code_verifier: None,
-            client_req: None,
+            pending: None,
+            pending_ttl: PENDING_TTL,
+            token_store: None,
         }
     }
 
@@ -279,6 +315,98 @@ impl Auth {
         self.refresh_expires_at = expiry;
     }
 
+    /// Use a different root for the oauth2 endpoints, e.g. a proxy or a local stand-in.
+    ///
+    /// The endpoints are `{url}/oauth2/authorize` and `{url}/oauth2/token`.
+    ///
+    /// Default: `https://myanimelist.net/v1`
+    pub fn set_base_url(&mut self, url: &str) -> Result<(), TokenError> {
+        let url = url.trim_end_matches('/');
+
+        self.set_auth_url(AuthUrl::new(format!("{url}{AUTH_PATH}"))?);
+        self.set_token_url(TokenUrl::new(format!("{url}{TOKEN_PATH}"))?);
+
+        Ok(())
+    }
+
+    /// Use a different url for the oauth2 authorization endpoint.
+    pub fn set_auth_url(&mut self, url: AuthUrl) {
+        self.client = self.client.clone().set_auth_uri(url);
+    }
+
+    /// Use a different url for the oauth2 token endpoint.
+    pub fn set_token_url(&mut self, url: TokenUrl) {
+        self.client = self.client.clone().set_token_uri(url);
+    }
+
+    /// The url mal redirects the user to after they authorized the app.
+    pub fn redirect_url(&self) -> Option<&RedirectUrl> {
+        self.client.redirect_uri()
+    }
+
+    /// The state of the request started by [Auth::authenticate], if one is pending.
+    pub fn pending_state(&self) -> Option<&CsrfToken> {
+        self.pending.as_ref().map(PendingAuthorization::state)
+    }
+
+    /// How long a [PendingAuthorization] can be finished with [Auth::authenticate_finish] or
+    /// [Auth::finish_with] after it was started.
+    ///
+    /// This only applies to authorizations started after it is set. Default: 10 minutes
+    pub fn set_pending_ttl(&mut self, ttl: Duration) {
+        self.pending_ttl = ttl;
+    }
+
+    /// Set a [TokenStore] the tokens are saved to every time they are refreshed or generated.
+    pub fn set_token_store(&mut self, store: impl TokenStore + 'static) {
+        self.token_store = Some(Arc::new(store));
+    }
+
+    /// Replace all tokens and their expiry times.
+    pub(crate) fn set_tokens_unchecked(&mut self, tokens: AuthTokens) {
+        self.set_access_token_unchecked(tokens.access_token);
+        self.set_refresh_token_unchecked(tokens.refresh_token);
+        self.set_expires_at_unchecked(tokens.expires_at);
+        self.set_refresh_expires_at_unchecked(tokens.refresh_expires_at);
+    }
+
+    /// Stop saving tokens to the [TokenStore], if one is set.
+    pub(crate) fn clear_token_store(&mut self) {
+        self.token_store = None;
+    }
+
+    /// Load previously saved tokens from the [TokenStore].
+    ///
+    /// Returns whether any tokens were found. If no store is set, this does nothing.
+    pub async fn load_tokens(&mut self) -> Result<bool, TokenError> {
+        let Some(store) = &self.token_store else {
+            return Ok(false);
+        };
+
+        let Some(tokens) = store.load().await? else {
+            return Ok(false);
+        };
+
+        self.set_tokens_unchecked(tokens);
+
+        Ok(true)
+    }
+
+    /// Load previously saved tokens from the [TokenStore].
+    #[cfg(feature = "blocking")]
+    pub fn load_tokens_blocking(&mut self) -> Result<bool, TokenError> {
+        crate::RUNTIME.block_on(self.load_tokens())
+    }
+
+    /// Save the current tokens to the [TokenStore], if one is set.
+    async fn save_tokens(&self) -> Result<(), TokenError> {
+        if let Some(store) = &self.token_store {
+            store.save(&self.to_tokens()).await?;
+        }
+
+        Ok(())
+    }
+
     /// Add an oauth2 scope. Use this before you generate a new token.
     pub fn add_scope(&mut self, scope: Scope) {
         self.scopes.push(scope);
@@ -293,7 +421,13 @@ impl Auth {
     ///
     /// If you want to keep state consistent if you're manually setting those, then make sure to set both
     /// the access token and its expiry time.
+    ///
+    /// An access token with an unknown expiry (0) is assumed to be valid.
     pub fn is_access_valid(&self) -> bool {
+        if self.expires_at == 0 {
+            return !self.access_token.secret().is_empty();
+        }
+
         (Utc::now().timestamp() as u64) < self.expires_at
     }
 
@@ -343,7 +477,7 @@ impl Auth {
         crate::RUNTIME.block_on(self.try_refresh())
     }
 
-    /// Time in utc seconds when access token expires.
+    /// Time in utc seconds when access token expires, or 0 if it is unknown.
     pub fn expires_at(&self) -> u64 {
         self.expires_at
     }
@@ -357,26 +491,23 @@ impl Auth {
     const DAYS: u64 = 31;
 
     /// Exchange refresh token for new access token.
+    ///
+    /// If saving the new tokens to the [TokenStore] fails, they are still used, and
+    /// [TokenError::Store] is returned.
     pub async fn refresh(&mut self) -> Result<(), TokenError> {
+        if self.refresh_token.secret().is_empty() {
+            return Err(TokenError::MissingRefreshToken);
+        }
+
         let token = self
             .client
             .exchange_refresh_token(&self.refresh_token)
             .request_async(&self.async_client)
-            .await
-            .map_err(|e| TokenError::OAuth2(e.to_string()))?;
-
-        self.set_expires_at_unchecked(
-            Utc::now().timestamp() as u64 + token.expires_in().unwrap().as_secs(),
-        );
-
-        // how many days the refresh token is valid for; docs say "1 month"
-        self.set_refresh_expires_at_unchecked(
-            Utc::now().timestamp() as u64 + (Self::DAYS * 24 * 60 * 60),
-        );
+            .await?;
 
-        self.set_access_token_unchecked(token.access_token().clone());
+        self.set_token_response(&token, true);
 
-        self.set_refresh_token_unchecked(token.refresh_token().unwrap().clone());
+        self.save_tokens().await?;
 
         Ok(())
     }
@@ -392,6 +523,9 @@ impl Auth {
     /// Returns both url and state belonging to this authenticate request. Client should visit the auth url and authenticate.
     /// After client auths and gets sent to the redirect url on your server, if their state matches
     /// the state returned from this method, call [`Auth::authenticate_finish`] with their authorization code.
+    ///
+    /// If another instance may receive the redirect, save [`ClientAuthRequest::pending`] and finish it
+    /// there with [`Auth::finish_with`].
     pub fn authenticate(&mut self) -> Result<ClientAuthRequest, TokenError> {
         let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_plain();
 
@@ -402,12 +536,24 @@ impl Auth {
             .set_pkce_challenge(pkce_challenge)
             .url();
 
-        let req = ClientAuthRequest { auth_url, state };
+        let created_at = Utc::now().timestamp() as u64;
 
-        self.pkce_code_verifier = Some(pkce_verifier.into_secret());
-        self.client_req = Some(req.clone());
+        let pending = PendingAuthorization {
+            state: state.clone(),
+            pkce_verifier: pkce_verifier.into_secret(),
+            scopes: self.scopes.clone(),
+            redirect_url: self.client.redirect_uri().cloned(),
+            created_at,
+            expires_at: created_at.saturating_add(self.pending_ttl.as_secs()),
+        };
+
+        self.pending = Some(pending.clone());
 
-        Ok(req)
+        Ok(ClientAuthRequest {
+            auth_url,
+            state,
+            pending,
+        })
     }
 
     /// So, a client has now authorized themselves, visited the redirect url on your server, and
@@ -419,57 +565,136 @@ impl Auth {
     /// ALWAYS input ONLY the state you received from the redirect url on your server.
     /// If you arbitrarily put in your own state and it matches, then this will verify
     /// the client even if their state was incorrect (which is a security issue)!
+    ///
+    /// Fails if the authorization expired (see [Auth::set_pending_ttl]).
     pub async fn authenticate_finish(
         &mut self,
         client_state: CsrfToken,
         auth_code: AuthorizationCode,
     ) -> Result<(), TokenError> {
+        let Some(pending) = self.pending.clone() else {
+            return Err(TokenError::PkceCodeVerifierMissing);
+        };
+
+        if pending.is_expired() {
+            self.pending.take();
+            return Err(TokenError::PendingExpired);
+        }
+
         // ensure state is correct
-        if self
-            .client_req
-            .as_ref()
-            .is_some_and(|state| state.state.secret() != client_state.secret())
-        {
+        if pending.state.secret() != client_state.secret() {
             return Err(TokenError::StateMismatch);
         }
 
-        let pkce_verifier = {
-            let Some(pkce_verifier) = &self.pkce_code_verifier else {
-                return Err(TokenError::PkceCodeVerifierMissing);
-            };
+        self.exchange_code(&pending, auth_code).await?;
 
-            PkceCodeVerifier::new(pkce_verifier.clone())
-        };
+        // request succeeded; we don't need it anymore
+        self.pending.take();
 
-        // now get access token
-        let Ok(token) = self
+        Ok(())
+    }
+
+    /// Finish an authorization which may have been started by another [Auth] (e.g. on another server
+    /// behind a load balancer), from the [PendingAuthorization] saved when it was started.
+    ///
+    /// Pass in the state and authorization code you received in the redirect url. Fails if the state
+    /// doesn't match the pending one's, or the pending authorization expired (see [Auth::set_pending_ttl]).
+    ///
+    /// ```rust,ignore
+    /// // on the instance starting the login
+    /// let request = auth.authenticate()?;
+    /// store.save(request.state().secret(), serde_json::to_string(request.pending())?);
+    ///
+    /// // on the instance receiving the redirect
+    /// let pending = serde_json::from_str(&store.take(state.secret()))?;
+    /// auth.finish_with(pending, state, code).await?;
+    /// ```
+    pub async fn finish_with(
+        &mut self,
+        pending: PendingAuthorization,
+        client_state: CsrfToken,
+        auth_code: AuthorizationCode,
+    ) -> Result<(), TokenError> {
+        if pending.is_expired() {
+            return Err(TokenError::PendingExpired);
+        }
+
+        if pending.state.secret() != client_state.secret() {
+            return Err(TokenError::StateMismatch);
+        }
+
+        self.exchange_code(&pending, auth_code).await
+    }
+
+    /// Finish an authorization which may have been started by another [Auth]. See [Auth::finish_with].
+    #[cfg(feature = "blocking")]
+    pub fn finish_with_blocking(
+        &mut self,
+        pending: PendingAuthorization,
+        client_state: CsrfToken,
+        auth_code: AuthorizationCode,
+    ) -> Result<(), TokenError> {
+        crate::RUNTIME.block_on(self.finish_with(pending, client_state, auth_code))
+    }
+
+    /// Exchange the authorization code for tokens.
+    async fn exchange_code(
+        &mut self,
+        pending: &PendingAuthorization,
+        auth_code: AuthorizationCode,
+    ) -> Result<(), TokenError> {
+        let pkce_verifier = PkceCodeVerifier::new(pending.pkce_verifier.clone());
+
+        let mut request = self
             .client
             .exchange_code(auth_code)
-            .set_pkce_verifier(pkce_verifier)
-            .request_async(&self.async_client)
-            .await
-        else {
-            return Err(TokenError::Access);
-        };
+            .set_pkce_verifier(pkce_verifier);
 
-        // request succeeded; we don't need these anymore
-        self.pkce_code_verifier.take();
-        self.client_req.take();
+        // the redirect url must be the one the authorization was started with
+        if let Some(redirect_url) = &pending.redirect_url {
+            request = request.set_redirect_uri(Cow::Borrowed(redirect_url));
+        }
 
-        self.set_expires_at_unchecked(
-            Utc::now().timestamp() as u64 + token.expires_in().unwrap().as_secs(),
-        );
+        // now get access token
+        let token = request.request_async(&self.async_client).await?;
 
-        // how many days the refresh token is valid for; docs say "1 month"
-        self.set_refresh_expires_at_unchecked(
-            Utc::now().timestamp() as u64 + (Self::DAYS * 24 * 60 * 60),
-        );
+        self.set_token_response(&token, false);
+
+        self.save_tokens().await?;
+
+        Ok(())
+    }
 
+    /// Take the tokens from a successful token response.
+    ///
+    /// Without an expiry, `expires_at` is 0 (unknown), and the access token is used until mal rejects it.
+    /// A refresh response without a refresh token means the current one stays valid, so it is kept. If
+    /// the first response has none, there is nothing to refresh with, and the user has to authenticate
+    /// again once the access token expires.
+    fn set_token_response(&mut self, token: &BasicTokenResponse, refreshing: bool) {
+        let now = Utc::now().timestamp() as u64;
+
+        let expires_at = token
+            .expires_in()
+            .map_or(0, |expires_in| now.saturating_add(expires_in.as_secs()));
+
+        self.set_expires_at_unchecked(expires_at);
         self.set_access_token_unchecked(token.access_token().clone());
 
-        self.set_refresh_token_unchecked(token.refresh_token().unwrap().clone());
+        match token.refresh_token() {
+            Some(refresh_token) => {
+                // how many days the refresh token is valid for; docs say "1 month"
+                self.set_refresh_expires_at_unchecked(now + (Self::DAYS * 24 * 60 * 60));
+                self.set_refresh_token_unchecked(refresh_token.clone());
+            }
 
-        Ok(())
+            None if refreshing => (),
+
+            None => {
+                self.set_refresh_expires_at_unchecked(0);
+                self.set_refresh_token_unchecked(RefreshToken::new(String::new()));
+            }
+        }
     }
 
     #[cfg(feature = "blocking")]
@@ -483,12 +708,243 @@ impl Auth {
     }
 }
 
+/// An [Auth] which can be shared between tasks and clients, e.g. in an [Arc] or by cloning it.
+///
+/// Refreshes are serialized: while one is in flight, everyone else wanting a fresh token waits for it
+/// and gets its result, instead of refreshing again and invalidating the refresh token it just got.
+/// If it fails, they all get its error as [TokenError::Shared]; a refresh nobody waited for returns its
+/// error as is. Subscribers are sent the tokens every time they change.
+///
+/// ```rust,ignore
+/// let auth = SharedAuth::new(auth);
+///
+/// // refreshes the access token first if it expired
+/// let token = auth.access_token().await?;
+///
+/// let mut updates = auth.subscribe();
+/// while updates.changed().await.is_ok() {
+///     save_tokens(&updates.borrow());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SharedAuth {
+    inner: Arc<SharedAuthInner>,
+}
+
+struct SharedAuthInner {
+    // held for the whole refresh, which serializes them
+    state: tokio::sync::Mutex<SharedAuthState>,
+    // the current tokens, readable without waiting for a refresh to finish
+    tokens: watch::Sender<AuthTokens>,
+    // how many callers are waiting for the state, e.g. for a refresh in flight
+    waiting: AtomicUsize,
+}
+
+struct SharedAuthState {
+    auth: Auth,
+    // the access token the last refresh replaced
+    replaced: Option<AccessToken>,
+    // the access token a refresh failed to replace, and its error, until every caller waiting for it got it
+    failed: Option<(AccessToken, Arc<TokenError>)>,
+}
+
+/// Counts a caller as waiting for the [SharedAuthState] until dropped.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn new(waiting: &'a AtomicUsize) -> Self {
+        waiting.fetch_add(1, Ordering::SeqCst);
+        Self(waiting)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl fmt::Debug for SharedAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedAuth")
+            .field("tokens", &*self.inner.tokens.borrow())
+            .finish_non_exhaustive()
+    }
+}
+
+impl From<Auth> for SharedAuth {
+    fn from(auth: Auth) -> Self {
+        Self::new(auth)
+    }
+}
+
+impl SharedAuth {
+    pub fn new(auth: Auth) -> Self {
+        let (tokens, _) = watch::channel(auth.to_tokens());
+
+        Self {
+            inner: Arc::new(SharedAuthInner {
+                state: tokio::sync::Mutex::new(SharedAuthState {
+                    auth,
+                    replaced: None,
+                    failed: None,
+                }),
+                tokens,
+                waiting: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// The current tokens. This doesn't wait for a refresh in flight.
+    pub fn tokens(&self) -> AuthTokens {
+        self.inner.tokens.borrow().clone()
+    }
+
+    /// Get notified of new tokens, e.g. to persist them. The receiver starts out with the current tokens.
+    pub fn subscribe(&self) -> watch::Receiver<AuthTokens> {
+        self.inner.tokens.subscribe()
+    }
+
+    /// Replace the tokens, e.g. after loading them from storage, and notify subscribers.
+    pub async fn set_tokens(&self, tokens: AuthTokens) {
+        let mut state = self.inner.state.lock().await;
+        self.replace_tokens(&mut state, tokens);
+    }
+
+    /// Replace the tokens without waiting, unless a refresh is in flight. Returns whether they were replaced.
+    ///
+    /// A client whose tokens were changed with [MalClient::set_tokens](crate::MalClient::set_tokens) hands
+    /// them over on its next refresh anyway, this only gets them to subscribers sooner.
+    pub(crate) fn try_set_tokens(&self, tokens: AuthTokens) -> bool {
+        let Ok(mut state) = self.inner.state.try_lock() else {
+            return false;
+        };
+
+        self.replace_tokens(&mut state, tokens);
+        true
+    }
+
+    fn replace_tokens(&self, state: &mut SharedAuthState, tokens: AuthTokens) {
+        state.auth.set_tokens_unchecked(tokens.clone());
+        state.replaced = None;
+        state.failed = None;
+
+        self.inner.tokens.send_replace(tokens);
+    }
+
+    /// Replace the tokens, e.g. after loading them from storage, and notify subscribers.
+    #[cfg(feature = "blocking")]
+    pub fn set_tokens_blocking(&self, tokens: AuthTokens) {
+        crate::RUNTIME.block_on(self.set_tokens(tokens))
+    }
+
+    /// A valid access token, which is refreshed first if it expired or is about to.
+    ///
+    /// If another task is already refreshing it, this waits for that refresh instead.
+    pub async fn access_token(&self) -> Result<AccessToken, TokenError> {
+        let tokens = self.tokens();
+        if !tokens.expires_soon() {
+            return Ok(tokens.access_token);
+        }
+
+        let (tokens, _) = self.refresh_stale(&tokens.access_token, None).await?;
+
+        Ok(tokens.access_token)
+    }
+
+    /// A valid access token, which is refreshed first if it expired or is about to.
+    #[cfg(feature = "blocking")]
+    pub fn access_token_blocking(&self) -> Result<AccessToken, TokenError> {
+        crate::RUNTIME.block_on(self.access_token())
+    }
+
+    /// Exchange the refresh token for a new access token, and return the new tokens.
+    ///
+    /// If another task is already refreshing, this waits for it and returns its tokens instead of
+    /// refreshing again.
+    pub async fn refresh(&self) -> Result<AuthTokens, TokenError> {
+        let stale = self.tokens().access_token;
+        let (tokens, _) = self.refresh_stale(&stale, None).await?;
+
+        Ok(tokens)
+    }
+
+    /// Exchange the refresh token for a new access token, and return the new tokens.
+    #[cfg(feature = "blocking")]
+    pub fn refresh_blocking(&self) -> Result<AuthTokens, TokenError> {
+        crate::RUNTIME.block_on(self.refresh())
+    }
+
+    /// Replace the access token `stale`, unless a refresh already did. Returns the current tokens, and
+    /// whether this call refreshed them.
+    ///
+    /// A client passes its own `tokens`, which `stale` comes from. If they aren't ours, e.g. because the
+    /// client's tokens were changed with [MalClient::set_tokens](crate::MalClient::set_tokens), they are
+    /// refreshed instead.
+    pub(crate) async fn refresh_stale(
+        &self,
+        stale: &AccessToken,
+        tokens: Option<AuthTokens>,
+    ) -> Result<(AuthTokens, bool), TokenError> {
+        let waiting = Waiting::new(&self.inner.waiting);
+        let mut state = self.inner.state.lock().await;
+        drop(waiting);
+
+        let others_waiting = self.inner.waiting.load(Ordering::SeqCst) > 0;
+
+        // the refresh this caller waited for failed, don't try again right away
+        if let Some((failed, error)) = &state.failed {
+            if failed.secret() == stale.secret() {
+                let error = error.clone();
+                if !others_waiting {
+                    state.failed = None;
+                }
+
+                return Err(TokenError::Shared(error));
+            }
+        }
+
+        if state.auth.access_token().secret() != stale.secret() {
+            let already_refreshed = state
+                .replaced
+                .as_ref()
+                .is_some_and(|replaced| replaced.secret() == stale.secret());
+
+            match tokens {
+                Some(tokens) if !already_refreshed => state.auth.set_tokens_unchecked(tokens),
+                _ => return Ok((state.auth.to_tokens(), false)),
+            }
+        }
+
+        match state.auth.refresh().await {
+            Ok(()) => (),
+            // mal already replaced the refresh token, so the new tokens have to be used regardless
+            Err(TokenError::Store(e)) => warn!(error = %e, "saving the refreshed tokens failed"),
+            Err(e) if self.inner.waiting.load(Ordering::SeqCst) == 0 => return Err(e),
+            Err(e) => {
+                let e = Arc::new(e);
+                state.failed = Some((stale.clone(), e.clone()));
+                return Err(TokenError::Shared(e));
+            }
+        }
+
+        state.replaced = Some(stale.clone());
+        state.failed = None;
+
+        let tokens = state.auth.to_tokens();
+        self.inner.tokens.send_replace(tokens.clone());
+
+        Ok((tokens, true))
+    }
+}
+
 /// This type represents a particular client's request to authenticate
 /// to your service.
 #[derive(Clone)]
 pub struct ClientAuthRequest {
     auth_url: Url,
     state: CsrfToken,
+    pending: PendingAuthorization,
 }
 
 impl ClientAuthRequest {
@@ -504,6 +960,82 @@ impl ClientAuthRequest {
     pub fn state(&self) -> &CsrfToken {
         &self.state
     }
+
+    /// What's needed to finish this request with [Auth::finish_with], on any [Auth].
+    ///
+    /// It holds the pkce verifier, so keep it somewhere only your servers can read.
+    pub fn pending(&self) -> &PendingAuthorization {
+        &self.pending
+    }
+}
+
+/// An authorization started with [Auth::authenticate] which isn't finished yet.
+///
+/// This can be serialized and stored (e.g. in a database or cache shared by your servers), so that
+/// whichever server receives the redirect can finish it with [Auth::finish_with].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PendingAuthorization {
+    state: CsrfToken,
+    pkce_verifier: String,
+    scopes: Vec<Scope>,
+    redirect_url: Option<RedirectUrl>,
+    // times in utc seconds
+    created_at: u64,
+    expires_at: u64,
+}
+
+impl fmt::Debug for PendingAuthorization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let PendingAuthorization {
+            state,
+            scopes,
+            redirect_url,
+            created_at,
+            expires_at,
+            ..
+        } = self;
+
+        f.debug_struct("PendingAuthorization")
+            .field("state", &state)
+            .field("pkce_verifier", &"[redacted]")
+            .field("scopes", &scopes)
+            .field("redirect_url", &redirect_url)
+            .field("created_at", &created_at)
+            .field("expires_at", &expires_at)
+            .finish()
+    }
+}
+
+impl PendingAuthorization {
+    /// The state mal sends back in the redirect url. Use it to look up the pending authorization.
+    pub fn state(&self) -> &CsrfToken {
+        &self.state
+    }
+
+    /// The scopes which were requested.
+    pub fn scopes(&self) -> &[Scope] {
+        &self.scopes
+    }
+
+    /// The redirect url the authorization was started with.
+    pub fn redirect_url(&self) -> Option<&RedirectUrl> {
+        self.redirect_url.as_ref()
+    }
+
+    /// Time in utc seconds when the authorization was started.
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    /// Time in utc seconds after which the authorization can't be finished anymore.
+    pub fn expires_at(&self) -> u64 {
+        self.expires_at
+    }
+
+    /// Whether it's too late to finish the authorization. Expired ones can be thrown away.
+    pub fn is_expired(&self) -> bool {
+        Utc::now().timestamp() as u64 >= self.expires_at
+    }
 }
 
 #[non_exhaustive]
@@ -523,4 +1055,493 @@ pub enum TokenError {
     StateMismatch,
     #[error("PkceCodeVerifier not set; please call authenticate() first")]
     PkceCodeVerifierMissing,
+    #[error("pending authorization expired")]
+    PendingExpired,
+    #[error("token store failed: {0}")]
+    Store(#[from] TokenStoreError),
+    /// mal rejected the authorization code or refresh token, e.g. because it expired, was already used
+    /// or was revoked. The user has to authenticate again.
+    #[error("grant rejected: {}", .description.as_deref().unwrap_or("invalid_grant"))]
+    InvalidGrant { description: Option<String> },
+    /// The request to the token endpoint failed before mal answered it.
+    #[error("token request failed: {0}")]
+    Network(#[source] BoxError),
+    /// mal's answer couldn't be parsed as a token response.
+    #[error("invalid token response: {0}")]
+    InvalidResponse(String),
+    /// There is no refresh token to refresh with, e.g. because mal didn't send one.
+    /// The user has to authenticate again.
+    #[error("no refresh token")]
+    MissingRefreshToken,
+    /// The error of a refresh by a [SharedAuth], which other callers waited for as well.
+    #[error("{0}")]
+    Shared(Arc<TokenError>),
+}
+
+impl From<TokenRequestError> for TokenError {
+    fn from(error: TokenRequestError) -> Self {
+        match error {
+            RequestTokenError::ServerResponse(response)
+                if *response.error() == BasicErrorResponseType::InvalidGrant =>
+            {
+                TokenError::InvalidGrant {
+                    description: response.error_description().cloned(),
+                }
+            }
+
+            RequestTokenError::ServerResponse(response) => TokenError::OAuth2(response.to_string()),
+            RequestTokenError::Request(HttpClientError::Reqwest(error)) => {
+                TokenError::Network(error)
+            }
+            RequestTokenError::Request(error) => TokenError::Network(Box::new(error)),
+            RequestTokenError::Parse(error, _) => TokenError::InvalidResponse(error.to_string()),
+            RequestTokenError::Other(error) => TokenError::InvalidResponse(error),
+        }
+    }
+}
+
+type TokenRequestError =
+    RequestTokenError<HttpClientError<oauth2::reqwest::Error>, BasicErrorResponse>;
+
+/// The future returned by [TokenStore] methods.
+pub type TokenStoreFuture<'a, T> =
+    Pin<Box<dyn Future<Output = Result<T, TokenStoreError>> + Send + 'a>>;
+
+/// Persistent storage for [AuthTokens].
+///
+/// Set it with [`Auth::set_token_store()`] or [`MalClientBuilder::token_store()`](crate::MalClientBuilder::token_store),
+/// and refreshed tokens will be saved automatically.
+pub trait TokenStore: Send + Sync {
+    /// Load the saved tokens. Returns `None` if nothing was saved yet.
+    fn load(&self) -> TokenStoreFuture<'_, Option<AuthTokens>>;
+
+    /// Save the tokens, replacing any previously saved ones.
+    fn save<'a>(&'a self, tokens: &'a AuthTokens) -> TokenStoreFuture<'a, ()>;
+
+    /// Remove the saved tokens.
+    fn clear(&self) -> TokenStoreFuture<'_, ()>;
+}
+
+impl<T: TokenStore + ?Sized> TokenStore for Arc<T> {
+    fn load(&self) -> TokenStoreFuture<'_, Option<AuthTokens>> {
+        (**self).load()
+    }
+
+    fn save<'a>(&'a self, tokens: &'a AuthTokens) -> TokenStoreFuture<'a, ()> {
+        (**self).save(tokens)
+    }
+
+    fn clear(&self) -> TokenStoreFuture<'_, ()> {
+        (**self).clear()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TokenStoreError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// A [TokenStore] that saves the tokens as json to a file.
+///
+/// Writes are atomic (the file is written next to the target, then renamed over it),
+/// and on unix the file is only readable by its owner (0600).
+#[derive(Debug, Clone)]
+pub struct FileTokenStore {
+    path: PathBuf,
+}
+
+impl FileTokenStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The file the tokens are saved to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A temporary file next to the target. It is unique, so concurrent saves (e.g. from another process)
+    /// don't write to the same one.
+    fn tmp_path(&self) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{:016x}.tmp", rand::random::<u64>()));
+        self.path.with_file_name(name)
+    }
+
+    async fn write(&self, data: &[u8]) -> io::Result<()> {
+        let tmp = self.tmp_path();
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(&tmp).await?;
+
+        let written = async {
+            file.write_all(data).await?;
+            file.sync_all().await?;
+            drop(file);
+
+            fs::rename(&tmp, &self.path).await
+        };
+
+        if let Err(e) = written.await {
+            _ = fs::remove_file(&tmp).await;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+}
+
+impl TokenStore for FileTokenStore {
+    fn load(&self) -> TokenStoreFuture<'_, Option<AuthTokens>> {
+        Box::pin(async move {
+            let data = match fs::read(&self.path).await {
+                Ok(data) => data,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+
+            Ok(Some(serde_json::from_slice(&data)?))
+        })
+    }
+
+    fn save<'a>(&'a self, tokens: &'a AuthTokens) -> TokenStoreFuture<'a, ()> {
+        Box::pin(async move {
+            let data = serde_json::to_vec(tokens)?;
+            self.write(&data).await?;
+            Ok(())
+        })
+    }
+
+    fn clear(&self) -> TokenStoreFuture<'_, ()> {
+        Box::pin(async move {
+            match fs::remove_file(&self.path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+/// A [TokenStore] that keeps the tokens in memory.
+#[derive(Debug, Default)]
+pub struct MemoryTokenStore {
+    tokens: Mutex<Option<AuthTokens>>,
+}
+
+impl MemoryTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TokenStore for MemoryTokenStore {
+    fn load(&self) -> TokenStoreFuture<'_, Option<AuthTokens>> {
+        let tokens = self.tokens.lock().unwrap().clone();
+        Box::pin(async move { Ok(tokens) })
+    }
+
+    fn save<'a>(&'a self, tokens: &'a AuthTokens) -> TokenStoreFuture<'a, ()> {
+        *self.tokens.lock().unwrap() = Some(tokens.clone());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn clear(&self) -> TokenStoreFuture<'_, ()> {
+        self.tokens.lock().unwrap().take();
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn tokens(access_token: &str) -> AuthTokens {
+        AuthTokens {
+            access_token: AccessToken::new(access_token.to_owned()),
+            refresh_token: RefreshToken::new(format!("{access_token}-refresh")),
+            expires_at: 1_700_000_000,
+            refresh_expires_at: 1_702_000_000,
+        }
+    }
+
+    fn assert_tokens(tokens: Option<AuthTokens>, access_token: &str) {
+        let tokens = tokens.expect("tokens were saved");
+        let expected = self::tokens(access_token);
+
+        assert_eq!(tokens.access_token.secret(), expected.access_token.secret());
+        assert_eq!(
+            tokens.refresh_token.secret(),
+            expected.refresh_token.secret()
+        );
+        assert_eq!(tokens.expires_at, expected.expires_at);
+        assert_eq!(tokens.refresh_expires_at, expected.refresh_expires_at);
+    }
+
+    #[test]
+    fn pending_authorization_keeps_what_was_requested() {
+        let mut auth = Auth::new(
+            ClientId::new("id".to_owned()),
+            ClientSecret::new("secret".to_owned()),
+            RedirectUrl::new("http://localhost:8765/callback".to_owned()).unwrap(),
+        );
+        auth.add_scope(Scope::new("write:users".to_owned()));
+
+        let request = auth.authenticate().unwrap();
+        let pending = request.pending();
+
+        assert_eq!(pending.state().secret(), request.state().secret());
+        assert_eq!(pending.scopes(), [Scope::new("write:users".to_owned())]);
+        assert_eq!(
+            pending.redirect_url().unwrap().as_str(),
+            "http://localhost:8765/callback"
+        );
+        assert_eq!(
+            pending.expires_at() - pending.created_at(),
+            PENDING_TTL.as_secs()
+        );
+
+        // another instance finishes it from what was saved
+        let saved = serde_json::to_string(pending).unwrap();
+        let restored: PendingAuthorization = serde_json::from_str(&saved).unwrap();
+
+        assert_eq!(restored.state().secret(), pending.state().secret());
+        assert_eq!(restored.pkce_verifier, pending.pkce_verifier);
+        assert_eq!(restored.scopes(), pending.scopes());
+        assert_eq!(restored.redirect_url(), pending.redirect_url());
+        assert_eq!(restored.expires_at(), pending.expires_at());
+
+        let debug = format!("{restored:?}");
+        assert!(debug.contains("write:users"));
+        assert!(!debug.contains(&pending.pkce_verifier));
+    }
+
+    #[tokio::test]
+    async fn token_response_without_expiry_or_refresh_token() {
+        let mut auth = Auth::from_auth_tokens(
+            tokens("old"),
+            ClientId::new("id".to_owned()),
+            ClientSecret::new("secret".to_owned()),
+            RedirectUrl::new("http://localhost/callback".to_owned()).unwrap(),
+        );
+
+        let response: BasicTokenResponse =
+            serde_json::from_str(r#"{ "access_token": "new", "token_type": "Bearer" }"#).unwrap();
+
+        // a refresh without a refresh token keeps the current one
+        auth.set_token_response(&response, true);
+        assert_eq!(auth.access_token().secret(), "new");
+        assert_eq!(auth.refresh_token().secret(), "old-refresh");
+
+        auth.set_token_response(&response, false);
+
+        let tokens = AuthTokens::from(&auth);
+        assert_eq!(tokens.access_token.secret(), "new");
+        assert!(tokens.refresh_token.secret().is_empty());
+        assert_eq!(tokens.expires_at, 0);
+        assert_eq!(tokens.refresh_expires_at, 0);
+
+        // the token is used until mal rejects it
+        assert!(auth.is_access_valid());
+        assert!(!tokens.expires_soon());
+        assert!(!auth.is_refresh_valid());
+
+        assert!(matches!(
+            auth.refresh().await,
+            Err(TokenError::MissingRefreshToken)
+        ));
+        assert_eq!(auth.access_token().secret(), "new");
+
+        auth.set_access_token_unchecked(AccessToken::new(String::new()));
+        assert!(!auth.is_access_valid());
+    }
+
+    #[tokio::test]
+    async fn memory_token_store() {
+        let store = MemoryTokenStore::new();
+        assert!(store.load().await.unwrap().is_none());
+
+        store.save(&tokens("first")).await.unwrap();
+        store.save(&tokens("second")).await.unwrap();
+        assert_tokens(store.load().await.unwrap(), "second");
+
+        store.clear().await.unwrap();
+        assert!(store.load().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn file_token_store() {
+        let dir = TempDir::new();
+        let store = FileTokenStore::new(dir.path().join("tokens.json"));
+
+        assert!(store.load().await.unwrap().is_none());
+        // clearing what isn't there is fine
+        store.clear().await.unwrap();
+
+        store.save(&tokens("first")).await.unwrap();
+        store.save(&tokens("second")).await.unwrap();
+        assert_tokens(store.load().await.unwrap(), "second");
+
+        // no temporary files are left behind
+        assert_eq!(dir.files(), ["tokens.json"]);
+
+        store.clear().await.unwrap();
+        assert!(store.load().await.unwrap().is_none());
+        assert!(dir.files().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn file_token_store_concurrent_saves() {
+        let dir = TempDir::new();
+        let store = FileTokenStore::new(dir.path().join("tokens.json"));
+
+        let saves = (0..64)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move { store.save(&tokens(&format!("token-{i}"))).await })
+            })
+            .collect::<Vec<_>>();
+
+        for save in saves {
+            save.await.unwrap().unwrap();
+        }
+
+        // whichever save won, the file holds all of its tokens
+        let saved = store.load().await.unwrap().unwrap();
+        let access_token = saved.access_token.secret().clone();
+        assert_tokens(Some(saved), &access_token);
+
+        assert_eq!(dir.files(), ["tokens.json"]);
+    }
+
+    #[tokio::test]
+    async fn file_token_store_invalid_file() {
+        let dir = TempDir::new();
+        let path = dir.path().join("tokens.json");
+        std::fs::write(&path, "not json").unwrap();
+
+        let store = FileTokenStore::new(path);
+        assert!(matches!(store.load().await, Err(TokenStoreError::Serde(_))));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn file_token_store_is_private() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = TempDir::new();
+        let store = FileTokenStore::new(dir.path().join("tokens.json"));
+        store.save(&tokens("first")).await.unwrap();
+
+        let mode = std::fs::metadata(store.path())
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[cfg(feature = "testing")]
+    mod with_server {
+        use futures_util::future::join_all;
+
+        use super::*;
+        use crate::testing::MockServer;
+
+        /// A [SharedAuth] with the server's tokens, whose access token is about to expire.
+        async fn expiring_auth(server: &MockServer, refresh_token: Option<&str>) -> SharedAuth {
+            let mut tokens = server.tokens();
+            tokens.expires_at = 1;
+            if let Some(refresh_token) = refresh_token {
+                tokens.refresh_token = RefreshToken::new(refresh_token.to_owned());
+            }
+
+            let auth = SharedAuth::new(server.auth());
+            auth.set_tokens(tokens).await;
+            auth
+        }
+
+        #[tokio::test]
+        async fn concurrent_callers_share_one_refresh() {
+            let server = MockServer::start().await.unwrap();
+            let auth = expiring_auth(&server, None).await;
+
+            let tokens = join_all((0..8).map(|_| auth.access_token())).await;
+            assert_eq!(server.request_count(), 1);
+
+            let current = server.tokens().access_token;
+            for token in tokens {
+                assert_eq!(token.unwrap().secret(), current.secret());
+            }
+            assert_eq!(auth.tokens().access_token.secret(), current.secret());
+
+            // the new token is valid, so it isn't refreshed again
+            auth.access_token().await.unwrap();
+            assert_eq!(server.request_count(), 1);
+        }
+
+        #[tokio::test]
+        async fn concurrent_callers_share_a_failed_refresh() {
+            let server = MockServer::start().await.unwrap();
+            let auth = expiring_auth(&server, Some("revoked")).await;
+
+            let results = join_all((0..8).map(|_| auth.access_token())).await;
+            assert_eq!(server.request_count(), 1);
+
+            for result in results {
+                let Err(TokenError::Shared(e)) = result else {
+                    panic!("not a shared error: {result:?}");
+                };
+                assert!(matches!(*e, TokenError::InvalidGrant { .. }));
+            }
+
+            // nobody waits for the next refresh, which tries again
+            let result = auth.access_token().await;
+            assert!(matches!(result, Err(TokenError::InvalidGrant { .. })));
+            assert_eq!(server.request_count(), 2);
+
+            // callers arriving together share a refresh again
+            let result = join_all((0..2).map(|_| auth.access_token())).await;
+            assert!(result.iter().all(Result::is_err));
+            assert_eq!(server.request_count(), 3);
+
+            // new tokens can be refreshed right away
+            auth.set_tokens(server.tokens()).await;
+            auth.refresh().await.unwrap();
+            assert_eq!(server.request_count(), 4);
+        }
+
+        #[tokio::test]
+        async fn subscribers_get_new_tokens() {
+            let server = MockServer::start().await.unwrap();
+            let auth = SharedAuth::new(server.auth());
+
+            let mut updates = auth.subscribe();
+            assert_eq!(
+                updates.borrow_and_update().access_token.secret(),
+                server.tokens().access_token.secret()
+            );
+
+            let refreshed = auth.refresh().await.unwrap();
+            assert!(updates.has_changed().unwrap());
+            assert_eq!(
+                updates.borrow_and_update().access_token.secret(),
+                refreshed.access_token.secret()
+            );
+
+            auth.set_tokens(tokens("loaded")).await;
+            assert!(updates.has_changed().unwrap());
+            assert_eq!(updates.borrow_and_update().access_token.secret(), "loaded");
+
+            // a failed refresh changes nothing
+            assert!(auth.refresh().await.is_err());
+            assert!(!updates.has_changed().unwrap());
+        }
+    }
 }
@@ -0,0 +1,930 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fmt,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use oauth2::AccessToken;
+use reqwest::{Method, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use tokio::fs;
+
+/// The endpoint a request goes to, which decides how long its response is cached.
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Endpoint {
+    /// `GET /anime`
+    AnimeList,
+    /// `GET /anime/{anime_id}`
+    AnimeDetails,
+    /// `GET /anime/ranking`
+    AnimeRanking,
+    /// `GET /anime/season/{year}/{season}`
+    AnimeSeason,
+    /// `GET /anime/suggestions`
+    AnimeSuggestions,
+    /// `GET /manga`
+    MangaList,
+    /// `GET /manga/{manga_id}`
+    MangaDetails,
+    /// `GET /manga/ranking`
+    MangaRanking,
+    /// `GET /users/@me`
+    UserInformation,
+    /// `GET /users/{user_name}/animelist` of any user but `@me`
+    UserAnimeList,
+    /// `GET /users/@me/animelist`
+    OwnAnimeList,
+    /// `GET /users/{user_name}/mangalist` of any user but `@me`
+    UserMangaList,
+    /// `GET /users/@me/mangalist`
+    OwnMangaList,
+    /// `GET /forum/boards`
+    ForumBoards,
+    /// `GET /forum/topics`
+    ForumTopics,
+    /// `GET /forum/topic/{topic_id}`
+    ForumTopicDetail,
+}
+
+impl Endpoint {
+    /// The endpoint at `path`, relative to the root of the api (e.g. `/anime/21`).
+    pub(crate) fn from_path(path: &str) -> Option<Self> {
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+        let endpoint = match segments.as_slice() {
+            ["anime"] => Self::AnimeList,
+            ["anime", "ranking"] => Self::AnimeRanking,
+            ["anime", "suggestions"] => Self::AnimeSuggestions,
+            ["anime", "season", _, _] => Self::AnimeSeason,
+            ["anime", _] => Self::AnimeDetails,
+            ["manga"] => Self::MangaList,
+            ["manga", "ranking"] => Self::MangaRanking,
+            ["manga", _] => Self::MangaDetails,
+            ["users", "@me"] => Self::UserInformation,
+            ["users", "@me", "animelist"] => Self::OwnAnimeList,
+            ["users", _, "animelist"] => Self::UserAnimeList,
+            ["users", "@me", "mangalist"] => Self::OwnMangaList,
+            ["users", _, "mangalist"] => Self::UserMangaList,
+            ["forum", "boards"] => Self::ForumBoards,
+            ["forum", "topics"] => Self::ForumTopics,
+            ["forum", "topic", _] => Self::ForumTopicDetail,
+            _ => return None,
+        };
+
+        Some(endpoint)
+    }
+
+    /// How long responses of this endpoint are cached by default. `None` if they aren't.
+    ///
+    /// | endpoint | ttl |
+    /// |---|---|
+    /// | anime/manga details, forum boards | 24h |
+    /// | anime/manga search and ranking, seasonal anime, suggestions | 1h |
+    /// | other users' lists, forum topics | 5min |
+    /// | `@me` and its lists | not cached |
+    pub fn default_ttl(self) -> Option<Duration> {
+        const HOUR: u64 = 60 * 60;
+
+        let secs = match self {
+            Self::AnimeDetails | Self::MangaDetails | Self::ForumBoards => 24 * HOUR,
+
+            Self::AnimeList
+            | Self::AnimeRanking
+            | Self::AnimeSeason
+            | Self::AnimeSuggestions
+            | Self::MangaList
+            | Self::MangaRanking => HOUR,
+
+            Self::UserAnimeList
+            | Self::UserMangaList
+            | Self::ForumTopics
+            | Self::ForumTopicDetail => 5 * 60,
+
+            Self::UserInformation | Self::OwnAnimeList | Self::OwnMangaList => return None,
+        };
+
+        Some(Duration::from_secs(secs))
+    }
+
+    const ALL: [Self; 16] = [
+        Self::AnimeList,
+        Self::AnimeDetails,
+        Self::AnimeRanking,
+        Self::AnimeSeason,
+        Self::AnimeSuggestions,
+        Self::MangaList,
+        Self::MangaDetails,
+        Self::MangaRanking,
+        Self::UserInformation,
+        Self::UserAnimeList,
+        Self::OwnAnimeList,
+        Self::UserMangaList,
+        Self::OwnMangaList,
+        Self::ForumBoards,
+        Self::ForumTopics,
+        Self::ForumTopicDetail,
+    ];
+}
+
+/// What a cached response is looked up by.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CacheKey {
+    endpoint: Endpoint,
+    method: String,
+    url: String,
+    identity: String,
+}
+
+impl CacheKey {
+    /// The key of a request to `url`, sent with `access_token` or the client id.
+    ///
+    /// The access token is hashed, so that it isn't written to a [DiskCache].
+    pub(crate) fn new(
+        endpoint: Endpoint,
+        method: &Method,
+        url: &Url,
+        access_token: Option<&AccessToken>,
+    ) -> Self {
+        let identity = match access_token {
+            Some(token) => format!("user:{}", hash(token.secret())),
+            None => "client".to_owned(),
+        };
+
+        Self {
+            endpoint,
+            method: method.to_string(),
+            url: url.to_string(),
+            identity,
+        }
+    }
+
+    pub fn endpoint(&self) -> Endpoint {
+        self.endpoint
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Who the request was sent as: `client` for requests with the client id,
+    /// or `user:<hash of the access token>`.
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+
+    /// The last segment of the url's path, e.g. the anime id of [Endpoint::AnimeDetails].
+    fn last_segment(&self) -> Option<String> {
+        let url = Url::parse(&self.url).ok()?;
+        let segment = url.path_segments()?.next_back()?;
+
+        Some(segment.to_owned())
+    }
+}
+
+/// A cached response body.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CachedResponse {
+    body: String,
+    expires_at: u64,
+}
+
+impl CachedResponse {
+    fn new(body: String, ttl: Duration) -> Self {
+        Self {
+            body,
+            expires_at: Utc::now().timestamp() as u64 + ttl.as_secs(),
+        }
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// When the response expires, in utc seconds.
+    pub fn expires_at(&self) -> u64 {
+        self.expires_at
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Utc::now().timestamp() as u64 >= self.expires_at
+    }
+}
+
+/// The future returned by [CacheStore] methods.
+pub type CacheStoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, CacheError>> + Send + 'a>>;
+
+/// A filter for [CacheStore::remove_where].
+pub type CacheKeyFilter<'a> = &'a (dyn Fn(&CacheKey) -> bool + Send + Sync);
+
+/// Where a [ResponseCache] keeps its responses.
+///
+/// Stores don't need to drop expired responses themselves, the cache removes them when it finds them.
+pub trait CacheStore: Send + Sync {
+    /// The response saved under `key`, if any.
+    fn get<'a>(&'a self, key: &'a CacheKey) -> CacheStoreFuture<'a, Option<CachedResponse>>;
+
+    /// Save `response` under `key`, replacing any response already saved under it.
+    fn put<'a>(&'a self, key: &'a CacheKey, response: CachedResponse) -> CacheStoreFuture<'a, ()>;
+
+    /// Remove every response whose key matches `filter`.
+    fn remove_where<'a>(&'a self, filter: CacheKeyFilter<'a>) -> CacheStoreFuture<'a, ()>;
+}
+
+impl<T: CacheStore + ?Sized> CacheStore for Arc<T> {
+    fn get<'a>(&'a self, key: &'a CacheKey) -> CacheStoreFuture<'a, Option<CachedResponse>> {
+        (**self).get(key)
+    }
+
+    fn put<'a>(&'a self, key: &'a CacheKey, response: CachedResponse) -> CacheStoreFuture<'a, ()> {
+        (**self).put(key, response)
+    }
+
+    fn remove_where<'a>(&'a self, filter: CacheKeyFilter<'a>) -> CacheStoreFuture<'a, ()> {
+        (**self).remove_where(filter)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// A [CacheStore] keeping the responses in memory.
+///
+/// Expired responses are dropped whenever a new one is saved. Once it holds [MemoryCache::capacity]
+/// responses, the one expiring first is dropped to make room for a new one.
+#[derive(Debug)]
+pub struct MemoryCache {
+    responses: Mutex<HashMap<CacheKey, CachedResponse>>,
+    capacity: usize,
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        Self {
+            responses: Mutex::default(),
+            capacity: 1000,
+        }
+    }
+}
+
+impl MemoryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many responses are kept at most.
+    ///
+    /// Default: 1000
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+}
+
+impl CacheStore for MemoryCache {
+    fn get<'a>(&'a self, key: &'a CacheKey) -> CacheStoreFuture<'a, Option<CachedResponse>> {
+        let response = self.responses.lock().unwrap().get(key).cloned();
+        Box::pin(async move { Ok(response) })
+    }
+
+    fn put<'a>(&'a self, key: &'a CacheKey, response: CachedResponse) -> CacheStoreFuture<'a, ()> {
+        let mut responses = self.responses.lock().unwrap();
+        responses.retain(|_, response| !response.is_expired());
+
+        while responses.len() >= self.capacity && !responses.contains_key(key) {
+            let first = responses
+                .iter()
+                .min_by_key(|(_, response)| response.expires_at)
+                .map(|(key, _)| key.clone());
+
+            let Some(first) = first else {
+                break;
+            };
+
+            responses.remove(&first);
+        }
+
+        responses.insert(key.clone(), response);
+
+        Box::pin(async { Ok(()) })
+    }
+
+    fn remove_where<'a>(&'a self, filter: CacheKeyFilter<'a>) -> CacheStoreFuture<'a, ()> {
+        self.responses.lock().unwrap().retain(|key, _| !filter(key));
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// A [CacheStore] saving every response as a json file in a directory.
+///
+/// The directory is created when the first response is saved. Expired responses, and temporary files
+/// left behind by saves which didn't finish, are removed by [DiskCache::evict_expired], which runs
+/// when a response is saved at most once every 10 minutes, and whenever responses are invalidated.
+///
+/// The file of a response is named after its key, so each file is only read once to match it against
+/// invalidations; responses saved by other processes sharing the directory are still found.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+    index: Arc<Mutex<DiskIndex>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DiskEntry {
+    key: CacheKey,
+    response: CachedResponse,
+}
+
+/// What a [DiskCache] knows of its files without reading them.
+#[derive(Debug, Default)]
+struct DiskIndex {
+    /// The key and expiry of the response in each file read or written, by file name.
+    entries: HashMap<OsString, (CacheKey, u64)>,
+    last_eviction: Option<Instant>,
+}
+
+impl DiskCache {
+    /// How often saving a response evicts the expired ones.
+    const EVICTION_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+    /// How old a temporary file is before it's taken to be left behind by a save which didn't finish.
+    const TMP_MAX_AGE: Duration = Duration::from_secs(60);
+
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            index: Arc::default(),
+        }
+    }
+
+    /// The directory the responses are saved in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Remove the expired responses, and the temporary files of saves which didn't finish.
+    pub async fn evict_expired(&self) -> Result<(), CacheError> {
+        self.index.lock().unwrap().last_eviction = Some(Instant::now());
+        self.remove_files(&|_| false).await
+    }
+
+    fn file_name(key: &CacheKey) -> String {
+        let CacheKey {
+            method,
+            url,
+            identity,
+            ..
+        } = key;
+
+        format!("{}.json", hash(&format!("{method} {url} {identity}")))
+    }
+
+    async fn read(path: &Path) -> Result<Option<DiskEntry>, CacheError> {
+        let data = match fs::read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Some(serde_json::from_slice(&data)?))
+    }
+
+    async fn remove(path: &Path) -> Result<(), CacheError> {
+        match fs::remove_file(path).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove the responses whose key matches `filter` or which expired, and old temporary files.
+    async fn remove_files(&self, filter: CacheKeyFilter<'_>) -> Result<(), CacheError> {
+        let mut files = match fs::read_dir(&self.dir).await {
+            Ok(files) => files,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let now = Utc::now().timestamp() as u64;
+
+        while let Some(file) = files.next_entry().await? {
+            let path = file.path();
+            let name = file.file_name();
+
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("json") => (),
+                Some("tmp") => {
+                    let age = file
+                        .metadata()
+                        .await
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok());
+
+                    if age.is_some_and(|age| age >= Self::TMP_MAX_AGE) {
+                        Self::remove(&path).await?;
+                    }
+
+                    continue;
+                }
+                _ => continue,
+            }
+
+            let indexed = self.index.lock().unwrap().entries.get(&name).cloned();
+
+            let remove = match indexed {
+                // the file name is a hash of the key, so the key of a file never changes
+                Some((key, _)) if filter(&key) => true,
+                Some((_, expires_at)) if expires_at > now => false,
+                // another process may have saved a newer response since, so expired files are read again
+                _ => match Self::read(&path).await {
+                    Ok(Some(entry)) => {
+                        let remove = filter(&entry.key) || entry.response.is_expired();
+                        let DiskEntry { key, response } = entry;
+
+                        self.index
+                            .lock()
+                            .unwrap()
+                            .entries
+                            .insert(name.clone(), (key, response.expires_at));
+
+                        remove
+                    }
+                    Ok(None) => false,
+                    // unreadable files can't be matched, so they're removed as well
+                    Err(_) => true,
+                },
+            };
+
+            if remove {
+                Self::remove(&path).await?;
+                self.index.lock().unwrap().entries.remove(&name);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl CacheStore for DiskCache {
+    fn get<'a>(&'a self, key: &'a CacheKey) -> CacheStoreFuture<'a, Option<CachedResponse>> {
+        Box::pin(async move {
+            let entry = Self::read(&self.dir.join(Self::file_name(key))).await?;
+
+            // the file name is a hash, so make sure it's really this key
+            Ok(entry
+                .filter(|entry| entry.key == *key)
+                .map(|entry| entry.response))
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a CacheKey, response: CachedResponse) -> CacheStoreFuture<'a, ()> {
+        Box::pin(async move {
+            let expires_at = response.expires_at;
+            let entry = DiskEntry {
+                key: key.clone(),
+                response,
+            };
+
+            let data = serde_json::to_vec(&entry)?;
+
+            fs::create_dir_all(&self.dir).await?;
+
+            // write next to the target and rename over it, so a reader never sees half a file.
+            // the temporary file is unique, so concurrent puts of the same key don't write to the same one
+            let name = Self::file_name(key);
+            let path = self.dir.join(&name);
+            let tmp = path.with_extension(format!("{:016x}.tmp", rand::random::<u64>()));
+            fs::write(&tmp, data).await?;
+
+            if let Err(e) = fs::rename(&tmp, &path).await {
+                _ = fs::remove_file(&tmp).await;
+                return Err(e.into());
+            }
+
+            let evict = {
+                let mut index = self.index.lock().unwrap();
+                index.entries.insert(name.into(), (key.clone(), expires_at));
+
+                index
+                    .last_eviction
+                    .is_none_or(|last| last.elapsed() >= Self::EVICTION_INTERVAL)
+            };
+
+            if evict {
+                self.evict_expired().await?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn remove_where<'a>(&'a self, filter: CacheKeyFilter<'a>) -> CacheStoreFuture<'a, ()> {
+        Box::pin(self.remove_files(filter))
+    }
+}
+
+/// Caches the responses of GET requests, so that data which rarely changes isn't fetched again every time.
+///
+/// Responses are looked up by the request's method, url, and who it was sent as (the client id, or the
+/// access token), and kept for a time depending on their [Endpoint] (see [Endpoint::default_ttl]).
+/// Only successful responses are cached.
+///
+/// Updating or deleting an entry of the user's anime or manga list removes the cached lists, user
+/// information, and details of that anime or manga.
+///
+/// ```rust,ignore
+/// let cache = ResponseCache::disk("cache/mal")
+///     .ttl(Endpoint::AnimeRanking, Some(Duration::from_secs(6 * 60 * 60)))
+///     .ttl(Endpoint::ForumTopics, None);
+///
+/// MalClientBuilder::new().cache(cache);
+/// ```
+///
+/// Errors of the store are logged and otherwise ignored; the request is sent to mal as if nothing was cached.
+#[derive(Clone)]
+pub struct ResponseCache {
+    store: Arc<dyn CacheStore>,
+    ttls: HashMap<Endpoint, Option<Duration>>,
+}
+
+impl fmt::Debug for ResponseCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("ttls", &self.ttls)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ResponseCache {
+    /// Cache responses in a [CacheStore].
+    pub fn new(store: impl CacheStore + 'static) -> Self {
+        let ttls = Endpoint::ALL
+            .into_iter()
+            .map(|endpoint| (endpoint, endpoint.default_ttl()))
+            .collect();
+
+        Self {
+            store: Arc::new(store),
+            ttls,
+        }
+    }
+
+    /// Cache responses in memory, see [MemoryCache].
+    pub fn memory() -> Self {
+        Self::new(MemoryCache::new())
+    }
+
+    /// Cache responses in files in `dir`, see [DiskCache].
+    pub fn disk(dir: impl Into<PathBuf>) -> Self {
+        Self::new(DiskCache::new(dir))
+    }
+
+    /// How long the responses of `endpoint` are cached. `None` doesn't cache them.
+    pub fn ttl(mut self, endpoint: Endpoint, ttl: Option<Duration>) -> Self {
+        self.ttls.insert(endpoint, ttl);
+        self
+    }
+
+    /// How long the responses of `endpoint` are cached, if they are.
+    pub fn ttl_for(&self, endpoint: Endpoint) -> Option<Duration> {
+        self.ttls.get(&endpoint).copied().flatten()
+    }
+
+    /// The cached response to the request with `key`, if it hasn't expired.
+    pub(crate) async fn get(&self, key: &CacheKey) -> Result<Option<CachedResponse>, CacheError> {
+        let Some(response) = self.store.get(key).await? else {
+            return Ok(None);
+        };
+
+        if response.is_expired() {
+            self.store.remove_where(&|k| k == key).await?;
+            return Ok(None);
+        }
+
+        Ok(Some(response))
+    }
+
+    /// Cache `body` as the response to the request with `key`, if its endpoint is cached.
+    pub(crate) async fn put(&self, key: &CacheKey, body: &str) -> Result<(), CacheError> {
+        let Some(ttl) = self.ttl_for(key.endpoint()) else {
+            return Ok(());
+        };
+
+        let response = CachedResponse::new(body.to_owned(), ttl);
+        self.store.put(key, response).await
+    }
+
+    /// Remove the cached responses of `endpoint`.
+    pub async fn invalidate(&self, endpoint: Endpoint) -> Result<(), CacheError> {
+        self.store
+            .remove_where(&|key| key.endpoint() == endpoint)
+            .await
+    }
+
+    /// Remove the cached responses of `endpoint`.
+    #[cfg(feature = "blocking")]
+    pub fn invalidate_blocking(&self, endpoint: Endpoint) -> Result<(), CacheError> {
+        crate::RUNTIME.block_on(self.invalidate(endpoint))
+    }
+
+    /// Remove every cached response.
+    pub async fn clear(&self) -> Result<(), CacheError> {
+        self.store.remove_where(&|_| true).await
+    }
+
+    /// Remove every cached response.
+    #[cfg(feature = "blocking")]
+    pub fn clear_blocking(&self) -> Result<(), CacheError> {
+        crate::RUNTIME.block_on(self.clear())
+    }
+
+    /// Remove the responses which changed when a list entry at `path` (e.g. `/anime/21/my_list_status`)
+    /// was updated or deleted.
+    pub(crate) async fn invalidate_list_status(&self, path: &str) -> Result<(), CacheError> {
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+        let (lists, details) = match segments.as_slice() {
+            ["anime", id, "my_list_status"] => (
+                [Endpoint::OwnAnimeList, Endpoint::UserAnimeList],
+                (Endpoint::AnimeDetails, *id),
+            ),
+            ["manga", id, "my_list_status"] => (
+                [Endpoint::OwnMangaList, Endpoint::UserMangaList],
+                (Endpoint::MangaDetails, *id),
+            ),
+            _ => return Ok(()),
+        };
+
+        self.store
+            .remove_where(&|key| {
+                let endpoint = key.endpoint();
+
+                lists.contains(&endpoint)
+                    || endpoint == Endpoint::UserInformation
+                    || (endpoint == details.0 && key.last_segment().as_deref() == Some(details.1))
+            })
+            .await
+    }
+}
+
+/// A hex sha-256 of `value`, truncated to 128 bits.
+///
+/// Unlike std's hashers it never changes between builds, so a [DiskCache] still finds its files.
+fn hash(value: &str) -> String {
+    Sha256::digest(value)[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, time::SystemTime};
+
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn key(path: &str) -> CacheKey {
+        let url = Url::parse(&format!("https://api.myanimelist.net/v2{path}")).unwrap();
+        CacheKey::new(Endpoint::from_path(path).unwrap(), &Method::GET, &url, None)
+    }
+
+    fn response(body: &str, ttl: u64) -> CachedResponse {
+        CachedResponse::new(body.to_owned(), Duration::from_secs(ttl))
+    }
+
+    fn json_files(dir: &TempDir) -> usize {
+        dir.files()
+            .iter()
+            .filter(|name| name.ends_with(".json"))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn disk_cache_saves_responses_by_key() {
+        let dir = TempDir::new();
+        let cache = DiskCache::new(dir.path().join("cache"));
+
+        cache
+            .put(&key("/anime/21"), response("one piece", 60))
+            .await
+            .unwrap();
+        cache
+            .put(&key("/anime/21"), response("again", 60))
+            .await
+            .unwrap();
+
+        let saved = cache.get(&key("/anime/21")).await.unwrap().unwrap();
+        assert_eq!(saved.body(), "again");
+        assert!(cache.get(&key("/anime/5114")).await.unwrap().is_none());
+
+        // another cache over the same directory finds it as well
+        let other = DiskCache::new(cache.dir());
+        assert!(other.get(&key("/anime/21")).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn disk_cache_removes_matching_responses() {
+        let dir = TempDir::new();
+        let cache = DiskCache::new(dir.path());
+
+        for path in ["/anime/21", "/anime/5114", "/manga/2", "/anime/ranking"] {
+            cache.put(&key(path), response(path, 60)).await.unwrap();
+        }
+
+        cache
+            .remove_where(&|key| key.endpoint() == Endpoint::AnimeDetails)
+            .await
+            .unwrap();
+
+        assert!(cache.get(&key("/anime/21")).await.unwrap().is_none());
+        assert!(cache.get(&key("/anime/5114")).await.unwrap().is_none());
+        assert!(cache.get(&key("/manga/2")).await.unwrap().is_some());
+
+        // files this cache hasn't read or written are matched too
+        let other = DiskCache::new(dir.path());
+        other
+            .remove_where(&|key| key.endpoint() == Endpoint::MangaDetails)
+            .await
+            .unwrap();
+
+        assert!(cache.get(&key("/manga/2")).await.unwrap().is_none());
+        assert_eq!(json_files(&dir), 1);
+
+        cache.remove_where(&|_| true).await.unwrap();
+        assert!(dir.files().is_empty());
+    }
+
+    #[tokio::test]
+    async fn disk_cache_evicts_expired_responses_and_left_over_tmp_files() {
+        let dir = TempDir::new();
+        let cache = DiskCache::new(dir.path());
+
+        cache
+            .put(&key("/anime/21"), response("fresh", 60))
+            .await
+            .unwrap();
+        cache
+            .put(&key("/anime/5114"), response("expired", 0))
+            .await
+            .unwrap();
+        cache
+            .put(&key("/manga/2"), response("expired", 0))
+            .await
+            .unwrap();
+
+        let old_tmp = dir.path().join("old.0123456789abcdef.tmp");
+        let new_tmp = dir.path().join("new.0123456789abcdef.tmp");
+        File::create(&new_tmp).unwrap();
+        File::create(&old_tmp)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(5 * 60))
+            .unwrap();
+
+        // the cache evicted when it saved its first response, so these are still there
+        assert_eq!(json_files(&dir), 3);
+
+        cache.evict_expired().await.unwrap();
+
+        let mut expected = vec![
+            DiskCache::file_name(&key("/anime/21")),
+            "new.0123456789abcdef.tmp".to_owned(),
+        ];
+        expected.sort();
+        assert_eq!(dir.files(), expected);
+    }
+
+    #[tokio::test]
+    async fn disk_cache_evicts_when_saving() {
+        let dir = TempDir::new();
+
+        let cache = DiskCache::new(dir.path());
+        cache
+            .put(&key("/anime/21"), response("fresh", 60))
+            .await
+            .unwrap();
+        cache
+            .put(&key("/anime/5114"), response("expired", 0))
+            .await
+            .unwrap();
+        cache
+            .put(&key("/manga/2"), response("expired", 0))
+            .await
+            .unwrap();
+        assert_eq!(json_files(&dir), 3);
+
+        // a new cache evicts on its first save
+        let cache = DiskCache::new(dir.path());
+        cache
+            .put(&key("/manga/13"), response("fresh", 60))
+            .await
+            .unwrap();
+
+        let mut expected = vec![
+            DiskCache::file_name(&key("/anime/21")),
+            DiskCache::file_name(&key("/manga/13")),
+        ];
+        expected.sort();
+        assert_eq!(dir.files(), expected);
+    }
+
+    #[cfg(feature = "testing")]
+    mod with_server {
+        use super::*;
+        use crate::{objects::WatchStatus, testing::MockServer, MalClient};
+
+        fn client(server: &MockServer, cache: ResponseCache) -> MalClient {
+            server.client_builder().cache(cache).build().unwrap()
+        }
+
+        async fn details(client: &MalClient, anime_id: u64) {
+            client
+                .anime()
+                .get()
+                .details()
+                .anime_id(anime_id)
+                .send()
+                .await
+                .unwrap();
+        }
+
+        #[tokio::test]
+        async fn serves_cached_responses_until_the_list_changes() {
+            let server = MockServer::start().await.unwrap();
+            let dir = TempDir::new();
+            let client = client(&server, ResponseCache::disk(dir.path()));
+
+            details(&client, 5114).await;
+            details(&client, 5114).await;
+            details(&client, 9253).await;
+            assert_eq!(server.request_count(), 2);
+
+            client
+                .user_animelist()
+                .put()
+                .anime_id(5114)
+                .status(WatchStatus::Completed)
+                .send()
+                .await
+                .unwrap();
+
+            // only the details of the updated anime are fetched again
+            details(&client, 5114).await;
+            details(&client, 9253).await;
+            assert_eq!(server.request_count(), 4);
+
+            client.cache().unwrap().clear().await.unwrap();
+            assert!(dir.files().is_empty());
+
+            details(&client, 9253).await;
+            assert_eq!(server.request_count(), 5);
+        }
+
+        #[tokio::test]
+        async fn expired_and_uncached_responses_are_fetched_again() {
+            let server = MockServer::start().await.unwrap();
+            let dir = TempDir::new();
+            let cache = ResponseCache::disk(dir.path())
+                .ttl(Endpoint::AnimeDetails, Some(Duration::ZERO))
+                .ttl(Endpoint::MangaDetails, None);
+            let client = client(&server, cache);
+
+            details(&client, 5114).await;
+            details(&client, 5114).await;
+            assert_eq!(server.request_count(), 2);
+
+            for _ in 0..2 {
+                client
+                    .manga()
+                    .get()
+                    .details()
+                    .manga_id(2)
+                    .send()
+                    .await
+                    .unwrap();
+            }
+
+            assert_eq!(server.request_count(), 4);
+            // the expired response was replaced by the latest one, which expired as well
+            assert_eq!(json_files(&dir), 1);
+        }
+    }
+}
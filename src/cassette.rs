@@ -0,0 +1,491 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use reqwest::{header::HeaderMap, Method, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::transport::{HttpRequest, HttpResponse};
+
+// headers which hold credentials or session cookies, and are never written to a cassette
+const SECRET_REQUEST_HEADERS: [&str; 3] = ["authorization", "x-mal-client-id", "cookie"];
+const SECRET_RESPONSE_HEADERS: [&str; 1] = ["set-cookie"];
+const REDACTED: &str = "[REDACTED]";
+
+/// Whether a [Cassette] records requests or replays them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Send requests to mal, and write every request and its response to the cassette.
+    Record,
+    /// Answer requests with the responses recorded in the cassette, without touching the network.
+    Replay,
+}
+
+/// A json file of recorded requests and their responses, to run tests against mal without network access.
+///
+/// Record the requests once against the real api, then replay them (e.g. in CI):
+///
+/// ```rust,ignore
+/// // record
+/// MalClientBuilder::new().cassette(Cassette::record("tests/cassettes/anime.json"));
+///
+/// // replay
+/// MalClientBuilder::new().cassette(Cassette::replay("tests/cassettes/anime.json")?);
+/// ```
+///
+/// Access tokens, client ids and cookies are redacted before they are written.
+///
+/// When replaying, a request is answered with the first recording which wasn't used yet and has the same
+/// method, path and query (ignoring the order of the query parameters). The host is not compared,
+/// so recordings also replay against a different [base url](crate::MalClientBuilder::base_url).
+/// Replayed requests skip the rate limiter.
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    interactions: Vec<Interaction>,
+    // which interactions were already replayed
+    used: Vec<bool>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RecordedRequest {
+    method: String,
+    url: String,
+    headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+}
+
+impl RecordedRequest {
+    /// Capture `request`, redacting its credentials.
+    pub(crate) fn new(request: &HttpRequest) -> Self {
+        let mut headers = headers_map(&request.headers);
+        redact(&mut headers, &SECRET_REQUEST_HEADERS);
+
+        let body = request
+            .body
+            .as_ref()
+            .map(|body| String::from_utf8_lossy(body).into_owned());
+
+        Self {
+            method: request.method.to_string(),
+            url: request.url.to_string(),
+            headers,
+            body,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    headers: BTreeMap<String, String>,
+    body: String,
+}
+
+impl Cassette {
+    /// Record every request to the file at `path`, replacing what it held.
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mode: CassetteMode::Record,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Replay the requests recorded in the file at `path`.
+    pub fn replay(path: impl Into<PathBuf>) -> Result<Self, CassetteError> {
+        let path = path.into();
+
+        let data = fs::read(&path)?;
+        let file = serde_json::from_slice::<CassetteFile>(&data)?;
+
+        let state = State {
+            used: vec![false; file.interactions.len()],
+            interactions: file.interactions,
+        };
+
+        Ok(Self {
+            path,
+            mode: CassetteMode::Replay,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// The file the requests are recorded to or replayed from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    /// Add a request and its response to the cassette, and write it to the file.
+    pub(crate) async fn record_exchange(
+        &self,
+        request: RecordedRequest,
+        response: &HttpResponse,
+    ) -> Result<(), CassetteError> {
+        let mut headers = headers_map(&response.headers);
+        redact(&mut headers, &SECRET_RESPONSE_HEADERS);
+
+        let mut state = self.state.lock().await;
+
+        state.interactions.push(Interaction {
+            request,
+            response: RecordedResponse {
+                status: response.status.as_u16(),
+                headers,
+                body: String::from_utf8_lossy(&response.body).into_owned(),
+            },
+        });
+
+        let file = CassetteFile {
+            interactions: state.interactions.clone(),
+        };
+
+        let data = serde_json::to_vec_pretty(&file)?;
+        self.write(&data).await?;
+
+        Ok(())
+    }
+
+    /// Replace the file with `data`, through a temporary file so a crash mid-write can't corrupt it.
+    async fn write(&self, data: &[u8]) -> io::Result<()> {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{:016x}.tmp", rand::random::<u64>()));
+        let tmp = self.path.with_file_name(name);
+
+        tokio::fs::write(&tmp, data).await?;
+
+        if let Err(e) = tokio::fs::rename(&tmp, &self.path).await {
+            _ = tokio::fs::remove_file(&tmp).await;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// The recorded response to `request`.
+    pub(crate) async fn play(&self, request: &HttpRequest) -> Result<HttpResponse, CassetteError> {
+        let mut state = self.state.lock().await;
+        let State { interactions, used } = &mut *state;
+
+        let key = match_key(request.method.as_str(), &request.url);
+
+        let found = interactions
+            .iter()
+            .zip(used.iter_mut())
+            .find(|(recorded, used)| {
+                let Ok(url) = Url::parse(&recorded.request.url) else {
+                    return false;
+                };
+
+                !**used && match_key(&recorded.request.method, &url) == key
+            });
+
+        let Some((recorded, used)) = found else {
+            return Err(CassetteError::NoMatch {
+                method: request.method.clone(),
+                url: request.url.clone(),
+            });
+        };
+
+        *used = true;
+
+        let response = &recorded.response;
+        let headers = response
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.parse().ok()?, value.parse().ok()?)))
+            .collect();
+
+        Ok(HttpResponse {
+            status: StatusCode::from_u16(response.status)
+                .map_err(|_| CassetteError::InvalidStatus(response.status))?,
+            headers,
+            body: response.body.clone().into_bytes(),
+        })
+    }
+}
+
+/// What a request is matched on: its method, path, and sorted query.
+fn match_key(method: &str, url: &Url) -> (String, String, Vec<(String, String)>) {
+    let mut query = url.query_pairs().into_owned().collect::<Vec<_>>();
+    query.sort();
+
+    (method.to_ascii_uppercase(), url.path().to_owned(), query)
+}
+
+fn headers_map(headers: &HeaderMap) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_owned())))
+        .collect()
+}
+
+fn redact(headers: &mut BTreeMap<String, String>, names: &[&str]) {
+    for name in names {
+        if let Some(value) = headers.get_mut(*name) {
+            *value = REDACTED.to_owned();
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CassetteError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("no recorded response left for {method} {url}")]
+    NoMatch { method: Method, url: Url },
+    #[error("invalid recorded status {0}")]
+    InvalidStatus(u16),
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn request(method: Method, url: &str) -> HttpRequest {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "authorization",
+            HeaderValue::from_static("Bearer secret-token"),
+        );
+        headers.insert(
+            "x-mal-client-id",
+            HeaderValue::from_static("secret-client-id"),
+        );
+        headers.insert(
+            "cookie",
+            HeaderValue::from_static("MALSESSIONID=secret-session"),
+        );
+        headers.insert("accept", HeaderValue::from_static("application/json"));
+
+        HttpRequest {
+            method,
+            url: Url::parse(url).unwrap(),
+            headers,
+            body: None,
+        }
+    }
+
+    fn response(body: &str) -> HttpResponse {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "set-cookie",
+            HeaderValue::from_static("MALSESSIONID=secret-session"),
+        );
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+
+        HttpResponse {
+            status: StatusCode::OK,
+            headers,
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    async fn record(cassette: &Cassette, request: &HttpRequest, body: &str) {
+        cassette
+            .record_exchange(RecordedRequest::new(request), &response(body))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn redacts_credentials_and_cookies() {
+        let dir = TempDir::new();
+        let cassette = Cassette::record(dir.path().join("cassette.json"));
+
+        let request = request(Method::GET, "https://api.myanimelist.net/v2/anime/21");
+        record(&cassette, &request, r#"{"id":21}"#).await;
+
+        let file = fs::read_to_string(cassette.path()).unwrap();
+        assert!(!file.contains("secret"), "{file}");
+        assert!(file.contains("application/json"));
+
+        let file = serde_json::from_str::<CassetteFile>(&file).unwrap();
+        let interaction = &file.interactions[0];
+        assert_eq!(interaction.request.headers["authorization"], REDACTED);
+        assert_eq!(interaction.request.headers["cookie"], REDACTED);
+        assert_eq!(interaction.response.headers["set-cookie"], REDACTED);
+
+        // only the cassette is left in the directory
+        assert_eq!(dir.files(), ["cassette.json"]);
+    }
+
+    #[tokio::test]
+    async fn replays_in_order_ignoring_query_order_and_host() {
+        let dir = TempDir::new();
+        let path = dir.path().join("cassette.json");
+
+        let recorder = Cassette::record(&path);
+        let url = "https://api.myanimelist.net/v2/anime?q=one&limit=2&fields=id%2Ctitle";
+        record(&recorder, &request(Method::GET, url), "first").await;
+        record(&recorder, &request(Method::GET, url), "second").await;
+
+        let cassette = Cassette::replay(&path).unwrap();
+        assert_eq!(cassette.mode(), CassetteMode::Replay);
+
+        let reordered = request(
+            Method::GET,
+            "http://127.0.0.1:1234/v2/anime?fields=id%2Ctitle&limit=2&q=one",
+        );
+
+        let first = cassette.play(&reordered).await.unwrap();
+        assert_eq!(first.status, StatusCode::OK);
+        assert_eq!(first.body, b"first");
+        assert_eq!(first.headers["content-type"], "application/json");
+
+        let second = cassette.play(&reordered).await.unwrap();
+        assert_eq!(second.body, b"second");
+
+        // every recording is used once
+        assert!(matches!(
+            cassette.play(&reordered).await,
+            Err(CassetteError::NoMatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn replay_matches_method_path_and_query_values() {
+        let dir = TempDir::new();
+        let path = dir.path().join("cassette.json");
+
+        let recorder = Cassette::record(&path);
+        let url = "https://api.myanimelist.net/v2/anime/21/my_list_status?status=watching";
+        record(&recorder, &request(Method::PUT, url), "{}").await;
+
+        let cassette = Cassette::replay(&path).unwrap();
+
+        for (method, url) in [
+            (Method::GET, url),
+            (
+                Method::PUT,
+                "https://api.myanimelist.net/v2/anime/22/my_list_status?status=watching",
+            ),
+            (
+                Method::PUT,
+                "https://api.myanimelist.net/v2/anime/21/my_list_status?status=dropped",
+            ),
+        ] {
+            assert!(
+                matches!(
+                    cassette.play(&request(method.clone(), url)).await,
+                    Err(CassetteError::NoMatch { .. })
+                ),
+                "{method} {url}"
+            );
+        }
+
+        assert!(cassette.play(&request(Method::PUT, url)).await.is_ok());
+    }
+
+    #[test]
+    fn replay_of_a_missing_or_invalid_file_fails() {
+        let dir = TempDir::new();
+        let path = dir.path().join("cassette.json");
+
+        assert!(matches!(Cassette::replay(&path), Err(CassetteError::Io(_))));
+
+        fs::write(&path, "not json").unwrap();
+        assert!(matches!(
+            Cassette::replay(&path),
+            Err(CassetteError::Serde(_))
+        ));
+    }
+
+    #[cfg(feature = "testing")]
+    mod with_server {
+        use super::*;
+        use crate::{
+            objects::{AnimeId, Username},
+            testing::{MockServer, MOCK_ACCESS_TOKEN, MOCK_CLIENT_ID},
+            MalClient,
+        };
+
+        async fn fetch(client: &MalClient) -> (AnimeId, usize) {
+            let anime = client
+                .anime()
+                .get()
+                .details()
+                .anime_id(5114)
+                .send()
+                .await
+                .unwrap();
+
+            let page = client
+                .user_animelist()
+                .get()
+                .user_name(Username::Me)
+                .limit(1)
+                .send()
+                .await
+                .unwrap();
+
+            (anime.id, page.data.len())
+        }
+
+        #[tokio::test]
+        async fn records_then_replays_offline() {
+            let dir = TempDir::new();
+            let path = dir.path().join("cassette.json");
+
+            let server = MockServer::start().await.unwrap();
+            let recorder = server
+                .client_builder()
+                .cassette(Cassette::record(&path))
+                .build()
+                .unwrap();
+
+            let recorded = fetch(&recorder).await;
+            let requests = server.request_count();
+            assert_eq!(requests, 2);
+
+            let file = fs::read_to_string(&path).unwrap();
+            assert!(!file.contains(MOCK_ACCESS_TOKEN));
+            assert!(!file.contains(MOCK_CLIENT_ID));
+
+            // replay against a server that is gone
+            let addr = server.addr();
+            drop(server);
+
+            let player = MalClient::builder()
+                .base_url(format!("http://{addr}/v2"))
+                .client_id(crate::ClientId::new(MOCK_CLIENT_ID.to_owned()))
+                .auth_tokens(crate::auth::AuthTokens::default())
+                .cassette(Cassette::replay(&path).unwrap())
+                .build()
+                .unwrap();
+
+            assert_eq!(fetch(&player).await, recorded);
+        }
+    }
+}
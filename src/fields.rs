@@ -0,0 +1,371 @@
+//! Typed fields for the `fields(...)` parameter of the api.
+//!
+//! Fields which hold other objects (e.g. `my_list_status` or `related_anime`) can select
+//! which of their own fields are returned. Leave the list empty to get mal's default fields.
+//!
+//! ```rust,ignore
+//! client
+//!     .anime()
+//!     .get()
+//!     .details()
+//!     .anime_id(1)
+//!     .fields([
+//!         AnimeField::Title,
+//!         AnimeField::my_list_status([AnimeListStatusField::Tags, AnimeListStatusField::Comments]),
+//!         AnimeField::related_anime([AnimeField::Title, AnimeField::MediaType]),
+//!     ]);
+//!
+//! // or everything an AnimeNode can hold
+//! client.anime().get().details().anime_id(1).fields(AnimeField::ALL_DETAILS);
+//! ```
+
+use std::{borrow::Borrow, fmt};
+
+use itertools::Itertools as _;
+use strum::{Display, IntoStaticStr};
+
+/// Join fields into the comma separated form mal expects.
+pub(crate) fn join<F: fmt::Display>(fields: impl IntoIterator<Item = impl Borrow<F>>) -> String {
+    fields.into_iter().map(|f| f.borrow().to_string()).join(",")
+}
+
+/// Write `name` followed by the `{sub,fields}` selection, if there is one.
+fn write_nested<F: fmt::Display>(
+    f: &mut fmt::Formatter,
+    name: &str,
+    sub_fields: &[F],
+) -> fmt::Result {
+    f.write_str(name)?;
+
+    if !sub_fields.is_empty() {
+        write!(f, "{{{}}}", join::<F>(sub_fields))?;
+    }
+
+    Ok(())
+}
+
+/// A field of [AnimeNode](crate::objects::AnimeNode).
+#[derive(Clone, Debug, PartialEq, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum AnimeField {
+    Id,
+    Title,
+    MainPicture,
+    AlternativeTitles,
+    StartDate,
+    EndDate,
+    Synopsis,
+    Mean,
+    Rank,
+    Popularity,
+    NumListUsers,
+    NumScoringUsers,
+    Nsfw,
+    CreatedAt,
+    UpdatedAt,
+    MediaType,
+    Status,
+    Genres,
+    MyListStatus(Vec<AnimeListStatusField>),
+    NumEpisodes,
+    StartSeason,
+    Broadcast,
+    Source,
+    AverageEpisodeDuration,
+    Rating,
+    Studios,
+    Pictures,
+    Background,
+    RelatedAnime(Vec<AnimeField>),
+    RelatedManga(Vec<MangaField>),
+    Recommendations(Vec<AnimeField>),
+    Statistics(Vec<StatisticsField>),
+    /// The list status of an entry in a user's anime list. Only for the user animelist endpoint.
+    ListStatus(Vec<AnimeListStatusField>),
+}
+
+impl AnimeField {
+    /// Every field mal returns on the anime details endpoint.
+    pub const ALL_DETAILS: &'static [AnimeField] = &[
+        AnimeField::Id,
+        AnimeField::Title,
+        AnimeField::MainPicture,
+        AnimeField::AlternativeTitles,
+        AnimeField::StartDate,
+        AnimeField::EndDate,
+        AnimeField::Synopsis,
+        AnimeField::Mean,
+        AnimeField::Rank,
+        AnimeField::Popularity,
+        AnimeField::NumListUsers,
+        AnimeField::NumScoringUsers,
+        AnimeField::Nsfw,
+        AnimeField::CreatedAt,
+        AnimeField::UpdatedAt,
+        AnimeField::MediaType,
+        AnimeField::Status,
+        AnimeField::Genres,
+        AnimeField::MyListStatus(Vec::new()),
+        AnimeField::NumEpisodes,
+        AnimeField::StartSeason,
+        AnimeField::Broadcast,
+        AnimeField::Source,
+        AnimeField::AverageEpisodeDuration,
+        AnimeField::Rating,
+        AnimeField::Studios,
+        AnimeField::Pictures,
+        AnimeField::Background,
+        AnimeField::RelatedAnime(Vec::new()),
+        AnimeField::RelatedManga(Vec::new()),
+        AnimeField::Recommendations(Vec::new()),
+        AnimeField::Statistics(Vec::new()),
+    ];
+
+    /// `my_list_status{...}`
+    pub fn my_list_status(fields: impl IntoIterator<Item = AnimeListStatusField>) -> Self {
+        Self::MyListStatus(fields.into_iter().collect())
+    }
+
+    /// `related_anime{...}`
+    pub fn related_anime(fields: impl IntoIterator<Item = AnimeField>) -> Self {
+        Self::RelatedAnime(fields.into_iter().collect())
+    }
+
+    /// `related_manga{...}`
+    pub fn related_manga(fields: impl IntoIterator<Item = MangaField>) -> Self {
+        Self::RelatedManga(fields.into_iter().collect())
+    }
+
+    /// `recommendations{...}`
+    pub fn recommendations(fields: impl IntoIterator<Item = AnimeField>) -> Self {
+        Self::Recommendations(fields.into_iter().collect())
+    }
+
+    /// `statistics{...}`
+    pub fn statistics(fields: impl IntoIterator<Item = StatisticsField>) -> Self {
+        Self::Statistics(fields.into_iter().collect())
+    }
+
+    /// `list_status{...}`
+    pub fn list_status(fields: impl IntoIterator<Item = AnimeListStatusField>) -> Self {
+        Self::ListStatus(fields.into_iter().collect())
+    }
+}
+
+impl fmt::Display for AnimeField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name: &str = self.into();
+
+        match self {
+            Self::MyListStatus(fields) | Self::ListStatus(fields) => write_nested(f, name, fields),
+            Self::RelatedAnime(fields) | Self::Recommendations(fields) => {
+                write_nested(f, name, fields)
+            }
+            Self::RelatedManga(fields) => write_nested(f, name, fields),
+            Self::Statistics(fields) => write_nested(f, name, fields),
+            _ => f.write_str(name),
+        }
+    }
+}
+
+/// A field of [MangaNode](crate::objects::MangaNode).
+#[derive(Clone, Debug, PartialEq, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum MangaField {
+    Id,
+    Title,
+    MainPicture,
+    AlternativeTitles,
+    StartDate,
+    EndDate,
+    Synopsis,
+    Mean,
+    Rank,
+    Popularity,
+    NumListUsers,
+    NumScoringUsers,
+    Nsfw,
+    CreatedAt,
+    UpdatedAt,
+    MediaType,
+    Status,
+    Genres,
+    MyListStatus(Vec<MangaListStatusField>),
+    NumVolumes,
+    NumChapters,
+    Authors(Vec<AuthorField>),
+    Pictures,
+    Background,
+    RelatedAnime(Vec<AnimeField>),
+    RelatedManga(Vec<MangaField>),
+    Recommendations(Vec<MangaField>),
+    Serialization,
+    /// The list status of an entry in a user's manga list. Only for the user mangalist endpoint.
+    ListStatus(Vec<MangaListStatusField>),
+}
+
+impl MangaField {
+    /// Every field mal returns on the manga details endpoint.
+    pub const ALL_DETAILS: &'static [MangaField] = &[
+        MangaField::Id,
+        MangaField::Title,
+        MangaField::MainPicture,
+        MangaField::AlternativeTitles,
+        MangaField::StartDate,
+        MangaField::EndDate,
+        MangaField::Synopsis,
+        MangaField::Mean,
+        MangaField::Rank,
+        MangaField::Popularity,
+        MangaField::NumListUsers,
+        MangaField::NumScoringUsers,
+        MangaField::Nsfw,
+        MangaField::CreatedAt,
+        MangaField::UpdatedAt,
+        MangaField::MediaType,
+        MangaField::Status,
+        MangaField::Genres,
+        MangaField::MyListStatus(Vec::new()),
+        MangaField::NumVolumes,
+        MangaField::NumChapters,
+        MangaField::Authors(Vec::new()),
+        MangaField::Pictures,
+        MangaField::Background,
+        MangaField::RelatedAnime(Vec::new()),
+        MangaField::RelatedManga(Vec::new()),
+        MangaField::Recommendations(Vec::new()),
+        MangaField::Serialization,
+    ];
+
+    /// `my_list_status{...}`
+    pub fn my_list_status(fields: impl IntoIterator<Item = MangaListStatusField>) -> Self {
+        Self::MyListStatus(fields.into_iter().collect())
+    }
+
+    /// `authors{...}`
+    pub fn authors(fields: impl IntoIterator<Item = AuthorField>) -> Self {
+        Self::Authors(fields.into_iter().collect())
+    }
+
+    /// `related_anime{...}`
+    pub fn related_anime(fields: impl IntoIterator<Item = AnimeField>) -> Self {
+        Self::RelatedAnime(fields.into_iter().collect())
+    }
+
+    /// `related_manga{...}`
+    pub fn related_manga(fields: impl IntoIterator<Item = MangaField>) -> Self {
+        Self::RelatedManga(fields.into_iter().collect())
+    }
+
+    /// `recommendations{...}`
+    pub fn recommendations(fields: impl IntoIterator<Item = MangaField>) -> Self {
+        Self::Recommendations(fields.into_iter().collect())
+    }
+
+    /// `list_status{...}`
+    pub fn list_status(fields: impl IntoIterator<Item = MangaListStatusField>) -> Self {
+        Self::ListStatus(fields.into_iter().collect())
+    }
+}
+
+impl fmt::Display for MangaField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name: &str = self.into();
+
+        match self {
+            Self::MyListStatus(fields) | Self::ListStatus(fields) => write_nested(f, name, fields),
+            Self::Authors(fields) => write_nested(f, name, fields),
+            Self::RelatedAnime(fields) => write_nested(f, name, fields),
+            Self::RelatedManga(fields) | Self::Recommendations(fields) => {
+                write_nested(f, name, fields)
+            }
+            _ => f.write_str(name),
+        }
+    }
+}
+
+/// A field of [User](crate::objects::User).
+#[derive(Copy, Clone, Debug, PartialEq, IntoStaticStr, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum UserField {
+    Id,
+    Name,
+    Picture,
+    Gender,
+    Birthday,
+    Location,
+    JoinedAt,
+    TimeZone,
+    IsSupporter,
+    AnimeStatistics,
+}
+
+impl UserField {
+    /// Every field of [User](crate::objects::User).
+    pub const ALL: &'static [UserField] = &[
+        UserField::Id,
+        UserField::Name,
+        UserField::Picture,
+        UserField::Gender,
+        UserField::Birthday,
+        UserField::Location,
+        UserField::JoinedAt,
+        UserField::TimeZone,
+        UserField::IsSupporter,
+        UserField::AnimeStatistics,
+    ];
+}
+
+/// A field of [AnimeMyListStatus](crate::objects::AnimeMyListStatus).
+#[derive(Copy, Clone, Debug, PartialEq, IntoStaticStr, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum AnimeListStatusField {
+    Status,
+    Score,
+    NumEpisodesWatched,
+    IsRewatching,
+    StartDate,
+    FinishDate,
+    Priority,
+    NumTimesRewatched,
+    RewatchValue,
+    Tags,
+    Comments,
+    UpdatedAt,
+}
+
+/// A field of [MangaMyListStatus](crate::objects::MangaMyListStatus).
+#[derive(Copy, Clone, Debug, PartialEq, IntoStaticStr, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum MangaListStatusField {
+    Status,
+    Score,
+    NumVolumesRead,
+    NumChaptersRead,
+    IsRereading,
+    StartDate,
+    FinishDate,
+    Priority,
+    NumTimesReread,
+    RereadValue,
+    Tags,
+    Comments,
+    UpdatedAt,
+}
+
+/// A field of [Person](crate::objects::Person), for manga authors.
+#[derive(Copy, Clone, Debug, PartialEq, IntoStaticStr, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum AuthorField {
+    Id,
+    FirstName,
+    LastName,
+}
+
+/// A field of [AnimeNodeStatistics](crate::objects::AnimeNodeStatistics).
+#[derive(Copy, Clone, Debug, PartialEq, IntoStaticStr, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum StatisticsField {
+    Status,
+    NumListUsers,
+}
@@ -0,0 +1,180 @@
+//! Just enough of an http/1.1 server to answer requests on localhost.
+//!
+//! Every connection serves a single request and is closed afterwards.
+
+// shared by the mock server and the loopback login, which each use only part of it
+#![cfg_attr(not(all(feature = "testing", feature = "loopback")), allow(dead_code))]
+
+use std::{collections::HashMap, io};
+
+use reqwest::{StatusCode, Url};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader},
+    net::TcpStream,
+};
+
+// requests to a local server are tiny; anything bigger than this is not for us
+const MAX_HEAD: u64 = 16 * 1024;
+const MAX_BODY: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub(crate) method: String,
+    /// The request target, resolved against `http://localhost`.
+    pub(crate) url: Url,
+    headers: HashMap<String, String>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    /// Get a header by its lowercase name.
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    pub(crate) fn query(&self) -> HashMap<String, String> {
+        self.url.query_pairs().into_owned().collect()
+    }
+
+    /// Parse an `application/x-www-form-urlencoded` body.
+    pub(crate) fn form(&self) -> HashMap<String, String> {
+        let body = String::from_utf8_lossy(&self.body);
+        let mut url = Url::parse("http://localhost").unwrap();
+        url.set_query(Some(&body));
+        url.query_pairs().into_owned().collect()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Response {
+    status: StatusCode,
+    content_type: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub(crate) fn json(status: StatusCode, body: &serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json; charset=UTF-8",
+            headers: Vec::new(),
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    pub(crate) fn html(status: StatusCode, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/html; charset=UTF-8",
+            headers: Vec::new(),
+            body: body.into().into_bytes(),
+        }
+    }
+
+    pub(crate) fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+pub(crate) async fn read_request(stream: &mut TcpStream) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+
+    // the request line and headers
+    let mut head = (&mut reader).take(MAX_HEAD);
+
+    let line = read_head_line(&mut head).await?;
+
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(invalid("malformed request line"));
+    };
+
+    let method = method.to_owned();
+    let url = Url::parse("http://localhost")
+        .unwrap()
+        .join(target)
+        .map_err(|_| invalid("malformed request target"))?;
+
+    let mut headers = HashMap::new();
+    loop {
+        let line = read_head_line(&mut head).await?;
+        if line.is_empty() {
+            return Err(invalid("connection closed before end of headers"));
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_owned());
+        }
+    }
+
+    let len = headers
+        .get("content-length")
+        .and_then(|len| len.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    if len > MAX_BODY {
+        return Err(invalid("request body too large"));
+    }
+
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await?;
+
+    Ok(Request {
+        method,
+        url,
+        headers,
+        body,
+    })
+}
+
+/// Read a line of the request head, which fails once the head gets too large.
+async fn read_head_line<R>(head: &mut tokio::io::Take<R>) -> io::Result<String>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = String::new();
+    head.read_line(&mut line).await?;
+
+    if !line.ends_with('\n') && head.limit() == 0 {
+        return Err(invalid("request head too large"));
+    }
+
+    Ok(line)
+}
+
+pub(crate) async fn write_response(stream: &mut TcpStream, response: Response) -> io::Result<()> {
+    let Response {
+        status,
+        content_type,
+        headers,
+        body,
+    } = response;
+
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default(),
+        body.len()
+    );
+
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
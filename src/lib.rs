@@ -1,26 +1,57 @@
 pub mod api;
 pub mod api_request;
 pub mod auth;
+pub mod cache;
+pub mod cassette;
+pub mod fields;
+#[cfg(any(feature = "testing", feature = "loopback"))]
+mod http_server;
+#[cfg(feature = "loopback")]
+pub mod loopback;
 pub mod objects;
+mod pagination;
+pub mod rate_limit;
+pub mod retry;
+pub mod service;
+pub mod sessions;
+#[cfg(test)]
+mod test_util;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod transport;
 
 #[cfg(feature = "blocking")]
 use std::sync::LazyLock;
+use std::{
+    fmt,
+    hash::Hash,
+    sync::{Arc, RwLock},
+};
 
 pub use oauth2::{
     AccessToken, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, RefreshToken,
     Scope,
 };
-use reqwest::{Client, ClientBuilder};
+use reqwest::{Client, ClientBuilder, Url};
 #[cfg(feature = "blocking")]
 use tokio::runtime::{Builder, Runtime};
+use tower::{Layer, Service};
+use tracing::debug;
 
 use crate::{
     api::{
         anime::AnimeApi, forum::ForumApi, manga::MangaApi, user::UserApi,
         user_animelist::UserAnimeListApi, user_mangalist::UserMangaListApi,
     },
-    api_request::ApiRequest,
-    auth::AuthTokens,
+    api_request::{ApiRequest, InFlight},
+    auth::{Auth, AuthTokens, SharedAuth, TokenError, TokenStore},
+    cache::ResponseCache,
+    cassette::Cassette,
+    rate_limit::{RateLimit, RateLimiter},
+    retry::RetryPolicy,
+    service::{MalRequest, MalService},
+    sessions::MalSessions,
+    transport::{BoxError, HttpResponse, HttpTransport, ReqwestTransport},
 };
 
 const BASE_URL: &str = "https://myanimelist.net/v1";
@@ -39,11 +70,50 @@ static RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
 /// For proper usage of the api, please read the myanimelist docs:
 ///
 /// <https://myanimelist.net/apiconfig/references/api/v2>
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct MalClient {
-    auth_tokens: AuthTokens,
-    http: Client,
+    auth_tokens: Arc<RwLock<AuthTokens>>,
+    // used to refresh the access token when it expires
+    auth: Option<SharedAuth>,
+    on_token_refresh: Option<TokenRefreshCallback>,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+    cache: Option<ResponseCache>,
+    // None if identical GETs aren't deduplicated
+    in_flight: Option<InFlight>,
+    cassette: Option<Arc<Cassette>>,
+    service: MalService,
     client_id: ClientId,
+    api_url: String,
+}
+
+type TokenRefreshCallback = Arc<dyn Fn(&AuthTokens) + Send + Sync + 'static>;
+
+impl fmt::Debug for MalClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let MalClient {
+            auth_tokens,
+            auth,
+            retry_policy,
+            rate_limiter,
+            cache,
+            cassette,
+            client_id,
+            api_url,
+            ..
+        } = self;
+
+        f.debug_struct("MalClient")
+            .field("auth_tokens", &auth_tokens)
+            .field("auth", &auth)
+            .field("retry_policy", &retry_policy)
+            .field("rate_limiter", &rate_limiter)
+            .field("cache", &cache)
+            .field("cassette", &cassette)
+            .field("client_id", &client_id)
+            .field("api_url", &api_url)
+            .finish()
+    }
 }
 
 impl MalClient {
@@ -55,8 +125,76 @@ impl MalClient {
         ApiRequest::new(self)
     }
 
-    pub fn set_tokens(&mut self, tokens: AuthTokens) {
-        self.auth_tokens = tokens;
+    /// The full url of the api endpoint at `path`.
+    pub(crate) fn api_url(&self, path: &str) -> String {
+        format!("{}{path}", self.api_url)
+    }
+
+    /// The path of `url` relative to the root of the api, e.g. `/anime/21`.
+    pub(crate) fn api_path<'u>(&self, url: &'u Url) -> &'u str {
+        let root = Url::parse(&self.api_url).ok();
+        let root = root
+            .as_ref()
+            .map_or("", |root| root.path().trim_end_matches('/'));
+
+        url.path().strip_prefix(root).unwrap_or(url.path())
+    }
+
+    pub fn set_tokens(&self, tokens: AuthTokens) {
+        *self.auth_tokens.write().unwrap() = tokens;
+    }
+
+    /// Get a copy of the tokens the client is currently using.
+    ///
+    /// If the client refreshed its access token, these are the new tokens.
+    pub fn tokens(&self) -> AuthTokens {
+        self.auth_tokens.read().unwrap().clone()
+    }
+
+    /// The rate limiter requests go through, if one was set.
+    ///
+    /// Use [RateLimiter::current_wait] to see how long the next request would wait.
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    /// The cache responses are kept in, if one was set.
+    ///
+    /// Use it to invalidate or clear cached responses.
+    pub fn cache(&self) -> Option<&ResponseCache> {
+        self.cache.as_ref()
+    }
+
+    /// Refresh the access token with the client's [Auth], if it has one.
+    ///
+    /// `stale` is the access token that was rejected or is about to expire. If another task already
+    /// replaced it, no refresh is done. Returns whether the client now has a different access token.
+    pub(crate) async fn refresh_tokens(&self, stale: &AccessToken) -> Result<bool, TokenError> {
+        let Some(auth) = &self.auth else {
+            return Ok(false);
+        };
+
+        let tokens = self.tokens();
+        if tokens.access_token.secret() != stale.secret() {
+            return Ok(true);
+        }
+
+        // the client's tokens are the source of truth, they may have been changed with set_tokens
+        let (tokens, refreshed) = auth.refresh_stale(stale, Some(tokens)).await?;
+        *self.auth_tokens.write().unwrap() = tokens.clone();
+
+        // another client sharing the auth already refreshed, and reported the new tokens
+        if !refreshed {
+            return Ok(true);
+        }
+
+        debug!(expires_at = tokens.expires_at, "refreshed access token");
+
+        if let Some(cb) = &self.on_token_refresh {
+            cb(&tokens);
+        }
+
+        Ok(true)
     }
 
     /// The anime endpoint
@@ -108,6 +246,18 @@ pub struct MalClientBuilder {
     auth_tokens: Option<AuthTokens>,
     client_id: Option<ClientId>,
     client: Option<Client>,
+    auth: Option<Auth>,
+    shared_auth: Option<SharedAuth>,
+    token_store: Option<Arc<dyn TokenStore>>,
+    on_token_refresh: Option<TokenRefreshCallback>,
+    retry_policy: Option<RetryPolicy>,
+    rate_limiter: Option<RateLimiter>,
+    cache: Option<ResponseCache>,
+    deduplicate_requests: Option<bool>,
+    cassette: Option<Cassette>,
+    transport: Option<Arc<dyn HttpTransport>>,
+    base_url: Option<String>,
+    layers: Vec<Box<dyn FnOnce(MalService) -> MalService>>,
     #[allow(clippy::complexity)]
     http_cb: Option<Box<dyn FnOnce(ClientBuilder) -> Result<Client, reqwest::Error> + 'static>>,
 }
@@ -119,7 +269,7 @@ impl MalClientBuilder {
 
     /// Provide [AuthTokens] for the client.
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// let auth: Auth = /*value*/;
     /// MalClientBuilder::new().auth_tokens(&auth);
     /// MalClientBuilder::new().auth_tokens(auth);
@@ -130,12 +280,154 @@ impl MalClientBuilder {
         self
     }
 
+    /// Provide an [Auth] the client can use to refresh its access token.
+    ///
+    /// When set, the access token is refreshed automatically shortly before it expires,
+    /// or when mal rejects it with a 401, after which the original request is retried once.
+    ///
+    /// If [MalClientBuilder::auth_tokens] is not set, the tokens currently held by `auth` are used.
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Refresh the access token with a [SharedAuth], which other clients may use as well.
+    ///
+    /// Works like [MalClientBuilder::auth], but clients sharing the auth never refresh at the same time;
+    /// when one refreshed the tokens, the others take its new tokens instead of refreshing again.
+    /// This takes precedence over [MalClientBuilder::auth], and can't be used with
+    /// [MalClientBuilder::token_store]; set a store on the [Auth] before sharing it.
+    pub fn shared_auth(mut self, auth: SharedAuth) -> Self {
+        self.shared_auth = Some(auth);
+        self
+    }
+
+    /// Save refreshed tokens to a [TokenStore]. Requires [MalClientBuilder::auth], building fails with
+    /// [MalClientError::Conflict] without it or with [MalClientBuilder::shared_auth].
+    ///
+    /// This replaces any store already set on the [Auth].
+    pub fn token_store(mut self, store: impl TokenStore + 'static) -> Self {
+        self.token_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Called with the new tokens every time the client refreshes its access token.
+    ///
+    /// Use this to persist the refreshed tokens.
+    pub fn on_token_refresh(mut self, cb: impl Fn(&AuthTokens) + Send + Sync + 'static) -> Self {
+        self.on_token_refresh = Some(Arc::new(cb));
+        self
+    }
+
+    /// How failed requests are retried. Defaults to [RetryPolicy::default()].
+    ///
+    /// Use [RetryPolicy::none()] to disable retries.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Limit how many requests the client sends. By default, requests are not limited.
+    ///
+    /// Requests using the client id and requests using an access token are limited separately.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(limit));
+        self
+    }
+
+    /// Use an existing [RateLimiter], e.g. to share one limit between several clients.
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Cache the responses of GET requests. By default, nothing is cached.
+    ///
+    /// ```rust,ignore
+    /// MalClientBuilder::new().cache(ResponseCache::memory());
+    /// ```
+    pub fn cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Let identical GETs (same url, sent with the same credentials) which are sent while one of them is
+    /// still in flight share its response, instead of each sending its own request. Every caller still
+    /// gets its own deserialized copy of the response. If the request can't be sent, the callers which
+    /// joined it get the error as [ApiError::Shared](api_request::ApiError::Shared).
+    ///
+    /// Default: true
+    pub fn deduplicate_requests(mut self, deduplicate: bool) -> Self {
+        self.deduplicate_requests = Some(deduplicate);
+        self
+    }
+
+    /// Record requests to a [Cassette], or replay them from it instead of sending them to mal.
+    pub fn cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// The root of the mal api, which every endpoint url is built from.
+    ///
+    /// Default: `https://api.myanimelist.net/v2`
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        let mut url = url.into();
+        url.truncate(url.trim_end_matches('/').len());
+
+        self.base_url = Some(url);
+        self
+    }
+
     /// Your myanimelist client id.
     pub fn client_id(mut self, client_id: ClientId) -> Self {
         self.client_id = Some(client_id);
         self
     }
 
+    /// Send requests with a custom [HttpTransport]. This takes precedence over
+    /// [MalClientBuilder::http] and [MalClientBuilder::http_builder].
+    pub fn transport(mut self, transport: impl HttpTransport + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Send requests through a [tower] layer, e.g. for timeouts, concurrency limits or metrics.
+    ///
+    /// The layer wraps the [MalService] which sends each attempt at a request. Layers added first are
+    /// the outermost, like with a [tower::ServiceBuilder], which can also be passed here as one layer.
+    ///
+    /// The client's own [retries](MalClientBuilder::retry_policy), [rate limit](MalClientBuilder::rate_limit)
+    /// and token refreshing always run outside of every layer, so no layer can go around or between them.
+    /// To order retries and rate limiting among your layers, disable the client's with [RetryPolicy::none()]
+    /// and by not setting a rate limit, and add a [RetryLayer](retry::RetryLayer) and a
+    /// [RateLimitLayer](rate_limit::RateLimitLayer) where you want them. The [cache](MalClientBuilder::cache)
+    /// and [request deduplication](MalClientBuilder::deduplicate_requests) have no layers; they answer
+    /// requests before the layers see them.
+    ///
+    /// ```rust,ignore
+    /// MalClientBuilder::new()
+    ///     .retry_policy(RetryPolicy::none())
+    ///     .layer(
+    ///         ServiceBuilder::new()
+    ///             .layer(RetryLayer::new(RetryPolicy::default()))
+    ///             .layer(RateLimitLayer::new(RateLimiter::new(RateLimit::per_second(1))))
+    ///             .concurrency_limit(4)
+    ///             .timeout(Duration::from_secs(10)),
+    ///     );
+    /// ```
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<MalService> + 'static,
+        L::Service: Service<MalRequest, Response = HttpResponse> + Clone + Send + Sync + 'static,
+        <L::Service as Service<MalRequest>>::Error: Into<BoxError>,
+        <L::Service as Service<MalRequest>>::Future: Send + 'static,
+    {
+        self.layers
+            .push(Box::new(move |service| service.layer(layer)));
+        self
+    }
+
     /// Provide a reqwest client. If specified, [MalClientBuilder::http_builder] takes precedence over this one. If both are empty, one will be automatically created.
     pub fn http(mut self, client: Client) -> Self {
         self.client = Some(client);
@@ -156,33 +448,108 @@ impl MalClientBuilder {
             return Err(MalClientError::Builder("client_id".to_owned()));
         };
 
-        let Some(auth) = self.auth_tokens else {
+        // the store would never be used
+        if self.token_store.is_some() {
+            if self.shared_auth.is_some() {
+                return Err(MalClientError::Conflict(
+                    "token_store cannot be combined with shared_auth, set the store on the Auth before sharing it"
+                        .to_owned(),
+                ));
+            }
+
+            if self.auth.is_none() {
+                return Err(MalClientError::Conflict(
+                    "token_store requires auth".to_owned(),
+                ));
+            }
+        }
+
+        let auth = self.shared_auth.or_else(|| {
+            let mut auth = self.auth?;
+            if let Some(store) = self.token_store {
+                auth.set_token_store(store);
+            }
+
+            Some(SharedAuth::new(auth))
+        });
+
+        let Some(auth_tokens) = self
+            .auth_tokens
+            .or_else(|| auth.as_ref().map(SharedAuth::tokens))
+        else {
             return Err(MalClientError::Builder("auth_tokens".to_owned()));
         };
 
-        let http = if let Some(cb) = self.http_cb {
-            let builder = ClientBuilder::new();
-            cb(builder)?
-        } else if let Some(client) = self.client {
-            client
+        let transport = if let Some(transport) = self.transport {
+            transport
         } else {
-            ClientBuilder::new()
-                .user_agent(concat!(
-                    env!("CARGO_PKG_NAME"),
-                    "/",
-                    env!("CARGO_PKG_VERSION"),
-                ))
-                .build()?
+            let http = if let Some(cb) = self.http_cb {
+                let builder = ClientBuilder::new();
+                cb(builder)?
+            } else if let Some(client) = self.client {
+                client
+            } else {
+                ClientBuilder::new()
+                    .user_agent(concat!(
+                        env!("CARGO_PKG_NAME"),
+                        "/",
+                        env!("CARGO_PKG_VERSION"),
+                    ))
+                    .build()?
+            };
+
+            Arc::new(ReqwestTransport::new(http))
         };
 
+        let cassette = self.cassette.map(Arc::new);
+
+        let service = self.layers.into_iter().rev().fold(
+            MalService::new(transport, cassette.clone()),
+            |service, layer| layer(service),
+        );
+
         let mal_client = MalClient {
-            auth_tokens: auth,
-            http,
+            auth_tokens: Arc::new(RwLock::new(auth_tokens)),
+            auth,
+            on_token_refresh: self.on_token_refresh,
+            retry_policy: self.retry_policy.unwrap_or_default(),
+            rate_limiter: self.rate_limiter,
+            cache: self.cache,
+            in_flight: self
+                .deduplicate_requests
+                .unwrap_or(true)
+                .then(InFlight::default),
+            cassette,
+            service,
             client_id,
+            api_url: self.base_url.unwrap_or_else(|| API_URL.to_owned()),
         };
 
         Ok(mal_client)
     }
+
+    /// Build [MalSessions], which hold the clients of many users, instead of a single client.
+    ///
+    /// All sessions share the http client, rate limiter, cache and other settings of this builder.
+    /// [MalClientBuilder::auth] is used to refresh each session's tokens, but its own tokens and
+    /// [MalClientBuilder::auth_tokens] are not used. [MalClientBuilder::token_store] is ignored, since
+    /// one store can't hold the tokens of every user; persist them with [MalSessions::on_token_refresh].
+    pub fn build_sessions<K>(mut self) -> Result<MalSessions<K>, MalClientError>
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static,
+    {
+        let mut auth = self.auth.take();
+        if let Some(auth) = &mut auth {
+            auth.clear_token_store();
+        }
+
+        self.token_store = None;
+        self.auth_tokens = Some(AuthTokens::default());
+
+        let template = self.build()?;
+
+        Ok(MalSessions::new(template, auth))
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -191,4 +558,7 @@ pub enum MalClientError {
     Reqwest(#[from] reqwest::Error),
     #[error("field '{0}' is required")]
     Builder(String),
+    /// Builder options were set which can't be used together.
+    #[error("{0}")]
+    Conflict(String),
 }
@@ -0,0 +1,598 @@
+//! Log in from a desktop or cli app, by receiving mal's redirect on a local http server.
+//!
+//! ```rust,ignore
+//! // the redirect url must be registered for your app on mal
+//! let redirect_url = RedirectUrl::new("http://127.0.0.1:8765/callback".to_owned())?;
+//! let mut auth = Auth::new(client_id, client_secret, redirect_url);
+//!
+//! // bind before sending the user off, so the redirect can't arrive before the server is up
+//! let server = LoopbackServer::bind(&auth).await?;
+//!
+//! let request = auth.authenticate()?;
+//! println!("Log in at {}", request.auth_url());
+//!
+//! server.finish(&mut auth).await?;
+//! ```
+
+use std::{future::Future, io, net::SocketAddr, pin::pin, time::Duration};
+
+use futures_util::{
+    future::{self, BoxFuture, Either, FutureExt as _},
+    stream::{FuturesUnordered, StreamExt as _},
+};
+use oauth2::url::Host;
+use reqwest::{StatusCode, Url};
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{
+    auth::{Auth, TokenError},
+    http_server::{self, Request, Response},
+    AuthorizationCode, CsrfToken,
+};
+
+const SUCCESS_PAGE: &str = "<!doctype html><html><head><title>Logged in</title></head>\
+<body><h1>Logged in to MyAnimeList</h1><p>You can close this window.</p></body></html>";
+
+// how long a connection may take to send its request; browsers open some ahead of time and never use them
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+const FAILURE_PAGE: &str = "<!doctype html><html><head><title>Login failed</title></head>\
+<body><h1>Login to MyAnimeList failed</h1><p>You can close this window and try again.</p></body></html>";
+
+/// A local http server which receives mal's redirect after the user authorized the app,
+/// and finishes the authentication with it.
+///
+/// It listens on the host and port of the [Auth]'s redirect url, which must be a loopback address
+/// (`localhost`, `127.0.0.1` or `[::1]`), and answers requests to its path. Other requests get a 404.
+///
+/// By default, [LoopbackServer::finish] gives up after 5 minutes. Dropping its future, or
+/// [LoopbackServer::cancel_on], cancels it.
+pub struct LoopbackServer {
+    listener: TcpListener,
+    path: String,
+    timeout: Option<Duration>,
+    cancel: Option<BoxFuture<'static, ()>>,
+}
+
+impl LoopbackServer {
+    /// Listen on the address of `auth`'s redirect url.
+    pub async fn bind(auth: &Auth) -> Result<Self, LoopbackError> {
+        let Some(redirect_url) = auth.redirect_url() else {
+            return Err(LoopbackError::InvalidRedirectUrl("not set".to_owned()));
+        };
+
+        let url = redirect_url.url();
+        let addr = loopback_addr(url)?;
+        let listener = TcpListener::bind(addr).await?;
+
+        Ok(Self {
+            listener,
+            path: url.path().to_owned(),
+            timeout: Some(Duration::from_secs(5 * 60)),
+            cancel: None,
+        })
+    }
+
+    /// Listen on the address of `auth`'s redirect url.
+    #[cfg(feature = "blocking")]
+    pub fn bind_blocking(auth: &Auth) -> Result<Self, LoopbackError> {
+        crate::RUNTIME.block_on(Self::bind(auth))
+    }
+
+    /// The address the server listens on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// How long to wait for the redirect. `None` waits forever.
+    ///
+    /// Default: 5 minutes
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Stop waiting for the redirect once `signal` completes, e.g. on ctrl-c.
+    pub fn cancel_on(mut self, signal: impl Future<Output = ()> + Send + 'static) -> Self {
+        self.cancel = Some(signal.boxed());
+        self
+    }
+
+    /// Wait for mal's redirect, and finish the authentication started by [Auth::authenticate] with it.
+    ///
+    /// The state in the redirect must match the pending request's. The user is shown a small page
+    /// telling them whether the login worked.
+    pub async fn finish(self, auth: &mut Auth) -> Result<(), LoopbackError> {
+        let Self {
+            listener,
+            path,
+            timeout,
+            cancel,
+        } = self;
+
+        let Some(pending_state) = auth.pending_state().cloned() else {
+            return Err(TokenError::PkceCodeVerifierMissing.into());
+        };
+
+        let redirect = async {
+            match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, wait_for_redirect(&listener, &path))
+                    .await
+                    .map_err(|_| LoopbackError::Timeout)?,
+                None => wait_for_redirect(&listener, &path).await,
+            }
+        };
+
+        let cancel = cancel.unwrap_or_else(|| future::pending().boxed());
+
+        let (mut stream, redirect) = match future::select(pin!(redirect), cancel).await {
+            Either::Left((redirect, _)) => redirect?,
+            Either::Right(_) => return Err(LoopbackError::Cancelled),
+        };
+
+        let result = match redirect {
+            Redirect::Denied { error, description } => {
+                Err(LoopbackError::Denied { error, description })
+            }
+
+            Redirect::Code { state, .. } if state.secret() != pending_state.secret() => {
+                Err(TokenError::StateMismatch.into())
+            }
+
+            Redirect::Code { code, state } => auth
+                .authenticate_finish(state, code)
+                .await
+                .map_err(Into::into),
+        };
+
+        let page = match result {
+            Ok(()) => Response::html(StatusCode::OK, SUCCESS_PAGE),
+            Err(_) => Response::html(StatusCode::BAD_REQUEST, FAILURE_PAGE),
+        };
+
+        // the user already sees mal's redirect, failing to show them our page is no reason to fail
+        _ = http_server::write_response(&mut stream, page).await;
+
+        result
+    }
+
+    /// Wait for mal's redirect, and finish the authentication started by [Auth::authenticate] with it.
+    #[cfg(feature = "blocking")]
+    pub fn finish_blocking(self, auth: &mut Auth) -> Result<(), LoopbackError> {
+        crate::RUNTIME.block_on(self.finish(auth))
+    }
+}
+
+/// What mal sent the user back with.
+enum Redirect {
+    Code {
+        code: AuthorizationCode,
+        state: CsrfToken,
+    },
+    Denied {
+        error: String,
+        description: Option<String>,
+    },
+}
+
+/// Answer requests until one is mal's redirect, and return it with the connection it came on.
+///
+/// Connections are read concurrently, so one which never sends a request can't hold up the redirect.
+async fn wait_for_redirect(
+    listener: &TcpListener,
+    path: &str,
+) -> Result<(TcpStream, Redirect), LoopbackError> {
+    let mut reading = FuturesUnordered::new();
+
+    loop {
+        let next = if reading.is_empty() {
+            Either::Left(listener.accept().await)
+        } else {
+            match future::select(pin!(listener.accept()), reading.next()).await {
+                Either::Left((accepted, _)) => Either::Left(accepted),
+                Either::Right((read, _)) => Either::Right(read),
+            }
+        };
+
+        let (mut stream, request) = match next {
+            Either::Left(accepted) => {
+                let (stream, _) = accepted?;
+                reading.push(read_request(stream));
+                continue;
+            }
+            Either::Right(Some(read)) => read,
+            Either::Right(None) => continue,
+        };
+
+        let Ok(request) = request else {
+            continue;
+        };
+
+        if request.method != "GET" || request.url.path() != path {
+            let response = Response::html(StatusCode::NOT_FOUND, "");
+            _ = http_server::write_response(&mut stream, response).await;
+            continue;
+        }
+
+        let mut query = request.query();
+
+        if let Some(error) = query.remove("error") {
+            let description = query.remove("error_description");
+            return Ok((stream, Redirect::Denied { error, description }));
+        }
+
+        let (Some(code), Some(state)) = (query.remove("code"), query.remove("state")) else {
+            let response = Response::html(StatusCode::BAD_REQUEST, FAILURE_PAGE);
+            _ = http_server::write_response(&mut stream, response).await;
+            continue;
+        };
+
+        let redirect = Redirect::Code {
+            code: AuthorizationCode::new(code),
+            state: CsrfToken::new(state),
+        };
+
+        return Ok((stream, redirect));
+    }
+}
+
+/// Read the request sent on `stream`, giving up after [READ_TIMEOUT].
+async fn read_request(mut stream: TcpStream) -> (TcpStream, io::Result<Request>) {
+    let request = tokio::time::timeout(READ_TIMEOUT, http_server::read_request(&mut stream))
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()));
+
+    (stream, request)
+}
+
+/// The address to listen on for `url`, if it is a loopback address.
+fn loopback_addr(url: &Url) -> Result<SocketAddr, LoopbackError> {
+    let invalid = || LoopbackError::InvalidRedirectUrl(url.to_string());
+
+    if url.scheme() != "http" {
+        return Err(invalid());
+    }
+
+    let port = url.port_or_known_default().ok_or_else(invalid)?;
+
+    let ip = match url.host() {
+        Some(Host::Domain("localhost")) => [127, 0, 0, 1].into(),
+        Some(Host::Ipv4(ip)) if ip.is_loopback() => ip.into(),
+        Some(Host::Ipv6(ip)) if ip.is_loopback() => ip.into(),
+        _ => return Err(invalid()),
+    };
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+#[derive(Debug, Error)]
+pub enum LoopbackError {
+    /// The redirect url isn't a plain http url on a loopback address.
+    #[error("redirect url is not a loopback http url: {0}")]
+    InvalidRedirectUrl(String),
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("no redirect received in time")]
+    Timeout,
+    #[error("waiting for the redirect was cancelled")]
+    Cancelled,
+    /// The user didn't authorize the app, or mal refused the request.
+    #[error("authorization failed: {error}")]
+    Denied {
+        error: String,
+        description: Option<String>,
+    },
+    #[error("{0}")]
+    Token(#[from] TokenError),
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt as _, AsyncWriteExt as _},
+        sync::oneshot,
+        task::JoinHandle,
+    };
+
+    use super::*;
+    use crate::{ClientId, ClientSecret, RedirectUrl};
+
+    fn auth() -> Auth {
+        // port 0 binds any free port, see LoopbackServer::local_addr
+        let redirect_url = RedirectUrl::new("http://127.0.0.1:0/callback".to_owned()).unwrap();
+        Auth::new(
+            ClientId::new("id".to_owned()),
+            ClientSecret::new("secret".to_owned()),
+            redirect_url,
+        )
+    }
+
+    /// Start authenticating, and return the state mal would redirect with.
+    fn start(auth: &mut Auth) -> String {
+        let request = auth.authenticate().unwrap();
+
+        request
+            .auth_url()
+            .query_pairs()
+            .find(|(name, _)| name == "state")
+            .unwrap()
+            .1
+            .into_owned()
+    }
+
+    /// Send `data` to the server, and return its response.
+    async fn send(addr: SocketAddr, data: impl Into<Vec<u8>>) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        // the server may close the connection before it read everything
+        _ = stream.write_all(&data.into()).await;
+
+        let mut response = Vec::new();
+        _ = stream.read_to_end(&mut response).await;
+
+        String::from_utf8(response).unwrap()
+    }
+
+    async fn get(addr: SocketAddr, target: &str) -> String {
+        send(
+            addr,
+            format!("GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+        )
+        .await
+    }
+
+    /// Send the requests one after the other, and return the status line of each response.
+    fn browse(addr: SocketAddr, targets: Vec<String>) -> JoinHandle<Vec<String>> {
+        tokio::spawn(async move {
+            let mut statuses = Vec::new();
+
+            for target in targets {
+                let response = get(addr, &target).await;
+                statuses.push(response.lines().next().unwrap_or_default().to_owned());
+            }
+
+            statuses
+        })
+    }
+
+    #[test]
+    fn only_listens_on_loopback_http_urls() {
+        let addr = |url: &str| loopback_addr(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            addr("http://localhost/callback").unwrap(),
+            "127.0.0.1:80".parse().unwrap()
+        );
+        assert_eq!(
+            addr("http://127.0.0.1:8765/callback").unwrap(),
+            "127.0.0.1:8765".parse().unwrap()
+        );
+        assert_eq!(
+            addr("http://[::1]:8765/callback").unwrap(),
+            "[::1]:8765".parse().unwrap()
+        );
+
+        for url in [
+            "https://localhost/callback",
+            "http://192.168.1.2/callback",
+            "http://example.com/callback",
+        ] {
+            assert!(matches!(
+                addr(url),
+                Err(LoopbackError::InvalidRedirectUrl(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_other_requests_until_the_redirect() {
+        let mut auth = auth();
+        let server = LoopbackServer::bind(&auth).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let state = start(&mut auth);
+
+        let browser = browse(
+            addr,
+            vec![
+                "/favicon.ico".to_owned(),
+                format!("/callback?state={state}"),
+                format!("/callback?error=access_denied&error_description=no&state={state}"),
+            ],
+        );
+
+        let result = server.finish(&mut auth).await;
+        assert!(matches!(
+            result,
+            Err(LoopbackError::Denied { error, description })
+                if error == "access_denied" && description.as_deref() == Some("no")
+        ));
+
+        assert_eq!(
+            browser.await.unwrap(),
+            [
+                "HTTP/1.1 404 Not Found",
+                "HTTP/1.1 400 Bad Request",
+                "HTTP/1.1 400 Bad Request",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_a_redirect_with_another_state() {
+        let mut auth = auth();
+        let server = LoopbackServer::bind(&auth).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        start(&mut auth);
+
+        let browser = browse(addr, vec!["/callback?code=abc&state=other".to_owned()]);
+
+        let result = server.finish(&mut auth).await;
+        assert!(matches!(
+            result,
+            Err(LoopbackError::Token(TokenError::StateMismatch))
+        ));
+        assert_eq!(browser.await.unwrap(), ["HTTP/1.1 400 Bad Request"]);
+    }
+
+    #[tokio::test]
+    async fn needs_a_pending_authorization() {
+        let mut auth = auth();
+        let server = LoopbackServer::bind(&auth).await.unwrap();
+
+        let result = server.finish(&mut auth).await;
+        assert!(matches!(
+            result,
+            Err(LoopbackError::Token(TokenError::PkceCodeVerifierMissing))
+        ));
+    }
+
+    #[tokio::test]
+    async fn drops_oversized_requests() {
+        let mut auth = auth();
+        let server = LoopbackServer::bind(&auth).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let state = start(&mut auth);
+
+        let browser = tokio::spawn(async move {
+            // a head over 16KB is never read to its end
+            let target = format!("/callback?error=access_denied&state={state}");
+            let padding = format!("X-Padding: {}\r\n", "a".repeat(32 * 1024));
+            let oversized = format!("GET {target} HTTP/1.1\r\n{padding}\r\n");
+
+            let response = send(addr, oversized).await;
+            let redirect = get(addr, &target).await;
+
+            (response, redirect)
+        });
+
+        let result = server.finish(&mut auth).await;
+        assert!(matches!(result, Err(LoopbackError::Denied { .. })));
+
+        let (response, redirect) = browser.await.unwrap();
+        assert!(response.is_empty());
+        assert!(redirect.starts_with("HTTP/1.1 400 Bad Request"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closes_idle_connections() {
+        let mut auth = auth();
+        let server = LoopbackServer::bind(&auth).await.unwrap().timeout(None);
+        let addr = server.local_addr().unwrap();
+        let state = start(&mut auth);
+
+        let browser = tokio::spawn(async move {
+            let start = tokio::time::Instant::now();
+
+            // like a connection a browser opened ahead of time, and never used
+            let mut idle = TcpStream::connect(addr).await.unwrap();
+            let closed = idle.read(&mut [0; 16]).await.unwrap();
+            let idle_for = start.elapsed();
+
+            get(
+                addr,
+                &format!("/callback?error=access_denied&state={state}"),
+            )
+            .await;
+
+            (closed, idle_for)
+        });
+
+        let result = server.finish(&mut auth).await;
+        assert!(matches!(result, Err(LoopbackError::Denied { .. })));
+
+        let (closed, idle_for) = browser.await.unwrap();
+        assert_eq!(closed, 0);
+        assert_eq!(idle_for, READ_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn idle_connections_dont_hold_up_the_redirect() {
+        let mut auth = auth();
+        let server = LoopbackServer::bind(&auth).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let state = start(&mut auth);
+
+        let idle = TcpStream::connect(addr).await.unwrap();
+        let browser = browse(
+            addr,
+            vec![format!("/callback?error=access_denied&state={state}")],
+        );
+
+        let result = tokio::time::timeout(Duration::from_secs(5), server.finish(&mut auth)).await;
+        assert!(matches!(result, Ok(Err(LoopbackError::Denied { .. }))));
+        assert_eq!(browser.await.unwrap(), ["HTTP/1.1 400 Bad Request"]);
+
+        drop(idle);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_timeout() {
+        let mut auth = auth();
+        let server = LoopbackServer::bind(&auth)
+            .await
+            .unwrap()
+            .timeout(Some(Duration::from_millis(50)));
+        start(&mut auth);
+
+        let result = server.finish(&mut auth).await;
+        assert!(matches!(result, Err(LoopbackError::Timeout)));
+        // the authorization can still be finished another way
+        assert!(auth.pending_state().is_some());
+    }
+
+    #[tokio::test]
+    async fn stops_when_cancelled() {
+        let mut auth = auth();
+        let (cancel, cancelled) = oneshot::channel();
+        let server = LoopbackServer::bind(&auth).await.unwrap().cancel_on(async {
+            _ = cancelled.await;
+        });
+        let addr = server.local_addr().unwrap();
+        start(&mut auth);
+
+        // a request that isn't the redirect doesn't end it
+        let browser = tokio::spawn(async move {
+            let response = get(addr, "/favicon.ico").await;
+            _ = cancel.send(());
+            response
+        });
+
+        let result = server.finish(&mut auth).await;
+        assert!(matches!(result, Err(LoopbackError::Cancelled)));
+        assert!(browser.await.unwrap().starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    #[cfg(feature = "testing")]
+    mod with_server {
+        use super::*;
+        use crate::testing::MockServer;
+
+        #[tokio::test]
+        async fn finishes_with_the_redirect() {
+            let mock = MockServer::start().await.unwrap();
+
+            let mut auth = auth();
+            auth.set_base_url(&mock.oauth_url()).unwrap();
+
+            let server = LoopbackServer::bind(&auth).await.unwrap();
+            let addr = server.local_addr().unwrap();
+            let state = start(&mut auth);
+
+            let browser = tokio::spawn(async move {
+                get(addr, &format!("/callback?code=abc&state={state}")).await
+            });
+
+            server.finish(&mut auth).await.unwrap();
+
+            assert_eq!(
+                auth.access_token().secret(),
+                mock.tokens().access_token.secret()
+            );
+            assert!(auth.pending_state().is_none());
+
+            let page = browser.await.unwrap();
+            assert!(page.starts_with("HTTP/1.1 200 OK"));
+            assert!(page.contains("Logged in to MyAnimeList"));
+        }
+    }
+}
@@ -1,6 +1,11 @@
-use chrono::prelude::{DateTime, NaiveTime, Utc};
+use std::{fmt, num::ParseIntError, str::FromStr};
+
+use chrono::{
+    prelude::{DateTime, NaiveDate, NaiveTime, Utc},
+    Datelike, Months,
+};
 use derive_more::Display as DeriveDisplay;
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use strum::{Display, EnumString, IntoStaticStr};
 
 #[derive(Clone, Debug, Serialize, Deserialize, DeriveDisplay, PartialEq)]
@@ -12,6 +17,98 @@ pub enum Username {
     User(String),
 }
 
+/// Defines an id newtype, which (de)serializes and displays like the bare number.
+macro_rules! id {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(
+            Copy, Clone, Debug, Serialize, Deserialize, DeriveDisplay, PartialEq, Eq, PartialOrd, Ord, Hash,
+        )]
+        #[serde(transparent)]
+        pub struct $name(pub u64);
+
+        impl From<u64> for $name {
+            fn from(id: u64) -> Self {
+                Self(id)
+            }
+        }
+
+        impl From<$name> for u64 {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseIntError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                s.parse().map(Self)
+            }
+        }
+    };
+}
+
+id!(
+    /// The id of an anime.
+    AnimeId
+);
+
+id!(
+    /// The id of a manga.
+    MangaId
+);
+
+id!(
+    /// The id of a user.
+    UserId
+);
+
+id!(
+    /// The id of a forum topic.
+    TopicId
+);
+
+id!(
+    /// The id of a forum board or subboard.
+    BoardId
+);
+
+id!(
+    /// The id of a person, e.g. a manga author.
+    PersonId
+);
+
+id!(
+    /// The id of an anime studio.
+    StudioId
+);
+
+id!(
+    /// The id of a genre.
+    GenreId
+);
+
+id!(
+    /// The id of a magazine a manga is serialized in.
+    MagazineId
+);
+
+id!(
+    /// The id of a forum post.
+    PostId
+);
+
+id!(
+    /// The id of a forum poll.
+    PollId
+);
+
+id!(
+    /// The id of an option of a forum poll.
+    PollOptionId
+);
+
 #[derive(
     Copy, Clone, Debug, Serialize, Deserialize, IntoStaticStr, EnumString, Display, PartialEq,
 )]
@@ -38,38 +135,49 @@ pub enum AnimeSort {
     AnimeId,
 }
 
-#[derive(
-    Copy, Clone, Serialize, Deserialize, Debug, IntoStaticStr, EnumString, Display, PartialEq,
-)]
+#[derive(Clone, Serialize, Deserialize, Debug, EnumString, Display, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
+#[non_exhaustive]
 pub enum WatchStatus {
     Watching,
     Completed,
     OnHold,
     Dropped,
     PlanToWatch,
+    /// A value mal sent which this version doesn't know yet.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
-#[derive(
-    Copy, Clone, Serialize, Deserialize, Debug, IntoStaticStr, EnumString, Display, PartialEq,
-)]
+#[derive(Clone, Serialize, Deserialize, Debug, EnumString, Display, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
+#[non_exhaustive]
 pub enum ReadStatus {
     Reading,
     Completed,
     OnHold,
     Dropped,
     PlanToRead,
+    /// A value mal sent which this version doesn't know yet.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
+/// A page of a ranking of anime ([AnimeRankingList]) or manga ([MangaRankingList]).
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
-pub struct RankingList {
-    pub data: Vec<MangaRankItem>,
+pub struct RankingList<N> {
+    pub data: Vec<RankItem<N>>,
     pub paging: Option<Paging>,
 }
 
+pub type AnimeRankingList = RankingList<AnimeNode>;
+
+pub type MangaRankingList = RankingList<MangaNode>;
+
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct SeasonList {
     pub data: Vec<SingleAnimeItem>,
@@ -129,14 +237,20 @@ pub struct MangaItem {
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
-pub struct MangaRankItem {
-    pub node: MangaNode,
+pub struct RankItem<N> {
+    pub node: N,
     pub ranking: Rank,
 }
 
+pub type AnimeRankItem = RankItem<AnimeNode>;
+
+pub type MangaRankItem = RankItem<MangaNode>;
+
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Rank {
     pub rank: u64,
+    /// The rank before the ranking was last updated. Not every ranking has it.
+    pub previous_rank: Option<u64>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
@@ -147,7 +261,7 @@ pub struct AnimeItem {
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct MangaNode {
-    pub id: u32,
+    pub id: MangaId,
     pub title: String,
     pub main_picture: Option<Picture>,
     pub alternative_titles: Option<AlternativeTitles>,
@@ -187,14 +301,14 @@ pub struct Author {
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Person {
-    pub id: u32,
+    pub id: PersonId,
     pub first_name: String,
     pub last_name: String,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct AnimeNode {
-    pub id: u32,
+    pub id: AnimeId,
     pub title: String,
     pub main_picture: Option<Picture>,
     pub alternative_titles: Option<AlternativeTitles>,
@@ -257,12 +371,27 @@ pub struct MangaRecommendation {
     pub num_recommendations: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-#[serde(rename_all = "lowercase")]
+#[derive(Debug, Serialize, Deserialize, Clone, EnumString, Display, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+#[non_exhaustive]
 pub enum RelationType {
     Prequel,
     Sequel,
+    AlternativeSetting,
+    AlternativeVersion,
+    SideStory,
+    ParentStory,
+    Summary,
+    FullStory,
+    SpinOff,
+    Adaptation,
+    Character,
     Other,
+    /// A value mal sent which this version doesn't know yet.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -280,9 +409,10 @@ pub struct AnimeNodeStatus {
     pub plan_to_watch: u64,
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, IntoStaticStr, EnumString, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, EnumString, Display, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
+#[non_exhaustive]
 pub enum Source {
     Other,
     Original,
@@ -301,11 +431,18 @@ pub enum Source {
     PictureBook,
     Radio,
     Music,
+    MixedMedia,
+    WebNovel,
+    /// A value mal sent which this version doesn't know yet.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, IntoStaticStr, EnumString, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, EnumString, Display, PartialEq)]
 #[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "lowercase")]
+#[non_exhaustive]
 pub enum Rating {
     G,
     PG,
@@ -317,53 +454,78 @@ pub enum Rating {
     #[serde(rename = "r+")]
     RPlus,
     RX,
+    /// A value mal sent which this version doesn't know yet.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, IntoStaticStr, EnumString, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, EnumString, Display, PartialEq)]
 #[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "lowercase")]
+#[non_exhaustive]
 pub enum MediaTypeAnime {
-    Unknown,
     TV,
     Ova,
     Movie,
     Special,
+    #[serde(rename = "tv_special")]
+    #[strum(serialize = "tv_special")]
+    TvSpecial,
     Ona,
     Music,
+    Pv,
+    Cm,
+    /// A value mal sent which this version doesn't know yet.
+    ///
+    /// This includes `unknown`, which mal sends when it doesn't know the media type.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, IntoStaticStr, EnumString, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, EnumString, Display, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
+#[non_exhaustive]
 pub enum MediaTypeManga {
-    Unknown,
     Manga,
     Novel,
+    LightNovel,
     OneShot,
     Doujinshi,
     Manhwa,
     Manhua,
     Oel,
+    /// A value mal sent which this version doesn't know yet.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, IntoStaticStr, EnumString, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, EnumString, Display, PartialEq)]
 #[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "lowercase")]
+#[non_exhaustive]
 pub enum Nsfw {
     White,
     Gray,
     Black,
+    /// A value mal sent which this version doesn't know yet.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Studio {
-    pub id: u32,
+    pub id: StudioId,
     pub name: String,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct MangaSerialization {
-    pub id: u32,
+    pub id: MagazineId,
     pub name: String,
 }
 
@@ -373,9 +535,10 @@ pub struct Broadcast {
     pub start_time: NaiveTime,
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, IntoStaticStr, EnumString, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, EnumString, Display, PartialEq)]
 #[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "lowercase")]
+#[non_exhaustive]
 pub enum DayOfWeek {
     Sunday,
     Monday,
@@ -384,49 +547,69 @@ pub enum DayOfWeek {
     Thursday,
     Friday,
     Saturday,
+    /// A value mal sent which this version doesn't know yet.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Season {
     pub year: u32,
     pub season: SeasonType,
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, IntoStaticStr, EnumString, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, EnumString, Display, PartialEq)]
 #[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "lowercase")]
+#[non_exhaustive]
 pub enum SeasonType {
     Winter,
     Spring,
     Summer,
     Fall,
+    /// A value mal sent which this version doesn't know yet.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, IntoStaticStr, EnumString, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, EnumString, Display, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
+#[non_exhaustive]
 pub enum AiringStatus {
     FinishedAiring,
     CurrentlyAiring,
     NotYetAired,
+    /// A value mal sent which this version doesn't know yet.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, IntoStaticStr, EnumString, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, EnumString, Display, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
+#[non_exhaustive]
 pub enum PublishingStatus {
     Finished,
     CurrentlyPublishing,
     NotYetPublished,
+    /// A value mal sent which this version doesn't know yet.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Genre {
-    pub id: u32,
+    pub id: GenreId,
     pub name: GenreType,
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, IntoStaticStr, EnumString, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, EnumString, Display, PartialEq)]
+#[non_exhaustive]
 pub enum GenreType {
     // genres
     Action,
@@ -569,6 +752,10 @@ pub enum GenreType {
     Seinen,
     Shoujo,
     Shounen,
+    /// A value mal sent which this version doesn't know yet.
+    #[serde(untagged)]
+    #[strum(default)]
+    Unknown(String),
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
@@ -606,6 +793,8 @@ pub struct AnimeMyListStatus {
 pub struct MangaMyListStatus {
     pub status: ReadStatus,
     pub score: u32,
+    // mal doesn't send this for manga
+    #[serde(default)]
     pub num_episodes_watched: u32,
     pub num_volumes_read: u32,
     pub num_chapters_read: u32,
@@ -635,6 +824,8 @@ pub struct AnimeListStatus {
 pub struct MangaListStatus {
     pub status: ReadStatus,
     pub score: u32,
+    // mal doesn't send this for manga
+    #[serde(default)]
     pub num_episodes_watched: u32,
     pub num_volumes_read: u32,
     pub num_chapters_read: u32,
@@ -655,8 +846,8 @@ pub struct AnimeListItem {
     pub tags: Vec<String>,
     pub comments: String,
     pub updated_at: DateTime<Utc>,
-    #[serde(deserialize_with = "date")]
-    pub start_date: PartialDate,
+    #[serde(default, deserialize_with = "date_opt")]
+    pub start_date: Option<PartialDate>,
 }
 
 // for parameter input on user mangalist
@@ -673,13 +864,13 @@ pub struct MangaListItem {
     pub tags: Vec<String>,
     pub comments: String,
     pub updated_at: DateTime<Utc>,
-    #[serde(deserialize_with = "date")]
-    pub start_date: PartialDate,
+    #[serde(default, deserialize_with = "date_opt")]
+    pub start_date: Option<PartialDate>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct User {
-    pub id: u32,
+    pub id: UserId,
     pub name: String,
     pub picture: String,
     pub gender: Option<String>,
@@ -779,7 +970,7 @@ pub struct ForumBoard {
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Board {
-    pub id: u32,
+    pub id: BoardId,
     pub title: String,
     pub description: String,
     pub subboards: Vec<SubBoard>,
@@ -787,7 +978,7 @@ pub struct Board {
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct SubBoard {
-    pub id: u32,
+    pub id: BoardId,
     pub title: String,
 }
 
@@ -806,7 +997,7 @@ pub struct Topic {
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Post {
-    pub id: u64,
+    pub id: PostId,
     pub number: u64,
     pub created_at: DateTime<Utc>,
     pub created_by: ForumUser,
@@ -816,14 +1007,14 @@ pub struct Post {
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct ForumUser {
-    pub id: u64,
+    pub id: UserId,
     pub name: String,
     pub forum_avatar: Option<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Poll {
-    pub id: u64,
+    pub id: PollId,
     pub question: String,
     pub closed: bool,
     pub options: Vec<PollOption>,
@@ -831,7 +1022,7 @@ pub struct Poll {
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct PollOption {
-    pub id: u64,
+    pub id: PollOptionId,
     pub text: String,
     pub votes: u64,
 }
@@ -850,7 +1041,7 @@ pub struct ForumTopics {
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct ForumTopic {
-    pub id: u64,
+    pub id: TopicId,
     pub title: String,
     pub created_at: DateTime<Utc>,
     pub created_by: ForumUser,
@@ -860,41 +1051,460 @@ pub struct ForumTopic {
     pub is_locked: bool,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// A date mal may only know the year, or year and month of, like `2017`, `2017-10` or `2017-10-05`.
+///
+/// It (de)serializes, displays and parses in that form. Dates compare by year, then month, then
+/// day, a less precise date coming before the more precise ones it covers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PartialDate {
-    pub year: u16,
-    pub month: Option<u16>,
-    pub day: Option<u16>,
+    year: u16,
+    month: Option<u16>,
+    // only set if the month is
+    day: Option<u16>,
+}
+
+/// How much of a [PartialDate] is known.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DatePrecision {
+    Year,
+    Month,
+    Day,
+}
+
+impl PartialDate {
+    /// A date, if the month and day exist (a day requires a month).
+    pub fn new(year: u16, month: Option<u16>, day: Option<u16>) -> Result<Self, PartialDateError> {
+        let date = Self { year, month, day };
+
+        if day.is_some() && month.is_none() {
+            return Err(PartialDateError::DayWithoutMonth);
+        }
+
+        if let Some(month) = month.filter(|month| !(1..=12).contains(month)) {
+            return Err(PartialDateError::Month(month));
+        }
+
+        if day.is_some() && date.to_naive_date_floor().is_none() {
+            return Err(PartialDateError::Day(date.to_string()));
+        }
+
+        Ok(date)
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    /// The month, from 1 to 12, if it is known.
+    pub fn month(&self) -> Option<u16> {
+        self.month
+    }
+
+    /// The day of the month, if it is known. Only set if the month is.
+    pub fn day(&self) -> Option<u16> {
+        self.day
+    }
+
+    pub fn precision(&self) -> DatePrecision {
+        match (self.month, self.day) {
+            (Some(_), Some(_)) => DatePrecision::Day,
+            (Some(_), None) => DatePrecision::Month,
+            (None, _) => DatePrecision::Year,
+        }
+    }
+
+    /// The first day the date covers, e.g. 2017-10-01 for `2017-10`. `None` if it isn't a valid date.
+    pub fn to_naive_date_floor(&self) -> Option<NaiveDate> {
+        let month = self.month.unwrap_or(1);
+        let day = self.month.and(self.day).unwrap_or(1);
+
+        NaiveDate::from_ymd_opt(self.year.into(), month.into(), day.into())
+    }
+
+    /// The last day the date covers, e.g. 2017-10-31 for `2017-10`. `None` if it isn't a valid date.
+    pub fn to_naive_date_ceil(&self) -> Option<NaiveDate> {
+        let Some(month) = self.month else {
+            return NaiveDate::from_ymd_opt(self.year.into(), 12, 31);
+        };
+
+        match self.day {
+            Some(day) => NaiveDate::from_ymd_opt(self.year.into(), month.into(), day.into()),
+            None => {
+                let first = NaiveDate::from_ymd_opt(self.year.into(), month.into(), 1)?;
+                first.checked_add_months(Months::new(1))?.pred_opt()
+            }
+        }
+    }
+}
+
+impl From<NaiveDate> for PartialDate {
+    fn from(date: NaiveDate) -> Self {
+        // years which don't fit are clamped, mal has no dates that far out
+        Self {
+            year: date.year().clamp(0, u16::MAX.into()) as u16,
+            month: Some(date.month() as u16),
+            day: Some(date.day() as u16),
+        }
+    }
+}
+
+impl fmt::Display for PartialDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}", self.year)?;
+
+        if let Some(month) = self.month {
+            write!(f, "-{month:02}")?;
+
+            if let Some(day) = self.day {
+                write!(f, "-{day:02}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for PartialDate {
+    type Err = PartialDateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PartialDateError::Format(s.to_owned());
+
+        let mut parts = s.split('-');
+        let mut next = || -> Result<Option<u16>, PartialDateError> {
+            match parts.next() {
+                Some(part) if !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()) => {
+                    part.parse().map(Some).map_err(|_| invalid())
+                }
+                Some(_) => Err(invalid()),
+                None => Ok(None),
+            }
+        };
+
+        let year = next()?.ok_or_else(invalid)?;
+        let month = next()?;
+        let day = next()?;
+
+        if next()?.is_some() {
+            return Err(invalid());
+        }
+
+        Self::new(year, month, day)
+    }
+}
+
+impl Serialize for PartialDate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PartialDate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PartialDateError {
+    #[error("invalid date '{0}', expected YYYY, YYYY-MM or YYYY-MM-DD")]
+    Format(String),
+    #[error("invalid month {0}")]
+    Month(u16),
+    #[error("{0} does not exist")]
+    Day(String),
+    #[error("a date with a day must have a month")]
+    DayWithoutMonth,
 }
 
+/// A date which may be missing, null or empty.
 fn date_opt<'de, D>(deserializer: D) -> Result<Option<PartialDate>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    Ok(Some(date(deserializer)?))
-}
-
-fn date<'de, D>(deserializer: D) -> Result<PartialDate, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s = String::deserialize(deserializer)?;
-    let num_hyphens = s.chars().filter(|c| *c == '-').count();
-    let split = s.split('-').collect::<Vec<_>>();
-
-    let date = PartialDate {
-        year: split[0].parse().unwrap(),
-        month: if num_hyphens >= 1 {
-            split[1].parse().ok()
-        } else {
-            None
-        },
-        day: if num_hyphens == 2 {
-            split[2].parse().ok()
-        } else {
-            None
-        },
-    };
-
-    Ok(date)
+    let s = Option::<String>::deserialize(deserializer)?;
+
+    match s.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(s) => s.parse().map(Some).map_err(de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the first page of /manga/ranking?ranking_type=all&limit=3&fields=rank,mean, as mal sends it
+    const MANGA_RANKING: &str = r#"{
+        "data": [
+            {
+                "node": {
+                    "id": 2,
+                    "title": "Berserk",
+                    "main_picture": {
+                        "medium": "https://cdn.myanimelist.net/images/manga/1/157897.jpg",
+                        "large": "https://cdn.myanimelist.net/images/manga/1/157897l.jpg"
+                    },
+                    "mean": 9.47,
+                    "rank": 1
+                },
+                "ranking": {"rank": 1, "previous_rank": 1}
+            },
+            {
+                "node": {
+                    "id": 1706,
+                    "title": "JoJo no Kimyou na Bouken Part 7: Steel Ball Run",
+                    "main_picture": {
+                        "medium": "https://cdn.myanimelist.net/images/manga/3/179882.jpg",
+                        "large": "https://cdn.myanimelist.net/images/manga/3/179882l.jpg"
+                    },
+                    "mean": 9.31,
+                    "rank": 2
+                },
+                "ranking": {"rank": 2, "previous_rank": 3}
+            },
+            {
+                "node": {
+                    "id": 656,
+                    "title": "Vagabond",
+                    "main_picture": {
+                        "medium": "https://cdn.myanimelist.net/images/manga/1/259070.jpg",
+                        "large": "https://cdn.myanimelist.net/images/manga/1/259070l.jpg"
+                    },
+                    "mean": 9.27,
+                    "rank": 3
+                },
+                "ranking": {"rank": 3}
+            }
+        ],
+        "paging": {
+            "next": "https://api.myanimelist.net/v2/manga/ranking?offset=3&ranking_type=all&limit=3&fields=rank%2Cmean"
+        }
+    }"#;
+
+    // the last page of a ranking has no next page
+    const MANGA_RANKING_LAST_PAGE: &str = r#"{
+        "data": [
+            {
+                "node": {"id": 13, "title": "One Piece"},
+                "ranking": {"rank": 5000}
+            }
+        ],
+        "paging": {
+            "previous": "https://api.myanimelist.net/v2/manga/ranking?offset=4998&ranking_type=all&limit=1"
+        }
+    }"#;
+
+    #[test]
+    fn manga_ranking_list() {
+        let list = serde_json::from_str::<MangaRankingList>(MANGA_RANKING).unwrap();
+
+        let ids = list
+            .data
+            .iter()
+            .map(|item| item.node.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [MangaId(2), MangaId(1706), MangaId(656)]);
+
+        let berserk = &list.data[0];
+        assert_eq!(berserk.node.title, "Berserk");
+        assert_eq!(berserk.node.mean, Some(9.47));
+        assert_eq!(berserk.node.rank, Some(1));
+        assert!(berserk.node.genres.is_none());
+
+        assert_eq!(
+            list.data[1].ranking,
+            Rank {
+                rank: 2,
+                previous_rank: Some(3),
+            }
+        );
+
+        let paging = list.paging.unwrap();
+        assert_eq!(paging.previous, None);
+        assert_eq!(
+            paging.next.as_deref(),
+            Some("https://api.myanimelist.net/v2/manga/ranking?offset=3&ranking_type=all&limit=3&fields=rank%2Cmean")
+        );
+    }
+
+    #[test]
+    fn manga_ranking_without_previous_rank() {
+        let list = serde_json::from_str::<MangaRankingList>(MANGA_RANKING).unwrap();
+
+        assert_eq!(
+            list.data[2].ranking,
+            Rank {
+                rank: 3,
+                previous_rank: None,
+            }
+        );
+    }
+
+    #[test]
+    fn manga_ranking_last_page() {
+        let list = serde_json::from_str::<MangaRankingList>(MANGA_RANKING_LAST_PAGE).unwrap();
+
+        assert_eq!(list.data.len(), 1);
+        assert_eq!(list.data[0].node.title, "One Piece");
+        assert_eq!(list.data[0].ranking.previous_rank, None);
+
+        let paging = list.paging.unwrap();
+        assert!(paging.previous.is_some());
+        assert_eq!(paging.next, None);
+    }
+
+    fn date(s: &str) -> PartialDate {
+        s.parse().unwrap()
+    }
+
+    fn naive(year: i32, month: u32, day: u32) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(year, month, day)
+    }
+
+    #[test]
+    fn partial_date_from_str() {
+        let year = date("2017");
+        assert_eq!((year.year(), year.month(), year.day()), (2017, None, None));
+        assert_eq!(year.precision(), DatePrecision::Year);
+
+        let month = date("2017-10");
+        assert_eq!(
+            (month.year(), month.month(), month.day()),
+            (2017, Some(10), None)
+        );
+        assert_eq!(month.precision(), DatePrecision::Month);
+
+        let day = date("2017-10-05");
+        assert_eq!(
+            (day.year(), day.month(), day.day()),
+            (2017, Some(10), Some(5))
+        );
+        assert_eq!(day.precision(), DatePrecision::Day);
+
+        assert_eq!(date("2017-10-05").to_string(), "2017-10-05");
+        assert_eq!(date("999-1").to_string(), "0999-01");
+    }
+
+    #[test]
+    fn partial_date_from_str_errors() {
+        for s in [
+            "",
+            "-",
+            "2017-",
+            "2017--05",
+            "2017-10-05-01",
+            "+2017",
+            " 2017",
+            "2017-x",
+            "99999",
+        ] {
+            assert_eq!(
+                s.parse::<PartialDate>(),
+                Err(PartialDateError::Format(s.to_owned())),
+                "{s:?}"
+            );
+        }
+
+        assert_eq!(
+            "2017-00".parse::<PartialDate>(),
+            Err(PartialDateError::Month(0))
+        );
+        assert_eq!(
+            "2017-13".parse::<PartialDate>(),
+            Err(PartialDateError::Month(13))
+        );
+        assert_eq!(
+            "2019-02-29".parse::<PartialDate>(),
+            Err(PartialDateError::Day("2019-02-29".to_owned()))
+        );
+        assert_eq!(
+            "2017-04-31".parse::<PartialDate>(),
+            Err(PartialDateError::Day("2017-04-31".to_owned()))
+        );
+        assert_eq!(
+            PartialDate::new(2017, None, Some(5)),
+            Err(PartialDateError::DayWithoutMonth)
+        );
+
+        assert!("2020-02-29".parse::<PartialDate>().is_ok());
+    }
+
+    #[test]
+    fn partial_date_opt() {
+        #[derive(Deserialize)]
+        struct Dated {
+            #[serde(default, deserialize_with = "date_opt")]
+            date: Option<PartialDate>,
+        }
+
+        let parse = |json: &str| serde_json::from_str::<Dated>(json).map(|dated| dated.date);
+
+        assert_eq!(parse(r#"{}"#).unwrap(), None);
+        assert_eq!(parse(r#"{"date": null}"#).unwrap(), None);
+        assert_eq!(parse(r#"{"date": ""}"#).unwrap(), None);
+        assert_eq!(parse(r#"{"date": "  "}"#).unwrap(), None);
+        assert_eq!(
+            parse(r#"{"date": "2017-10"}"#).unwrap(),
+            Some(date("2017-10"))
+        );
+        assert!(parse(r#"{"date": "2017-13"}"#).is_err());
+        assert!(parse(r#"{"date": "soon"}"#).is_err());
+    }
+
+    #[test]
+    fn partial_date_ord() {
+        let mut dates = [
+            "2017-02",
+            "2017-01-01",
+            "2018",
+            "2017",
+            "2016-12-31",
+            "2017-01",
+        ]
+        .map(date);
+        dates.sort();
+
+        assert_eq!(
+            dates.map(|date| date.to_string()),
+            [
+                "2016-12-31",
+                "2017",
+                "2017-01",
+                "2017-01-01",
+                "2017-02",
+                "2018"
+            ]
+        );
+
+        assert!(date("2017-10-31") < date("2017-11"));
+        assert!(date("2017-12") > date("2017-01-31"));
+    }
+
+    #[test]
+    fn partial_date_floor_and_ceil() {
+        assert_eq!(date("2017").to_naive_date_floor(), naive(2017, 1, 1));
+        assert_eq!(date("2017").to_naive_date_ceil(), naive(2017, 12, 31));
+
+        assert_eq!(date("2017-10").to_naive_date_floor(), naive(2017, 10, 1));
+        assert_eq!(date("2017-10").to_naive_date_ceil(), naive(2017, 10, 31));
+        assert_eq!(date("2017-04").to_naive_date_ceil(), naive(2017, 4, 30));
+
+        // december's last day is in the same year
+        assert_eq!(date("2017-12").to_naive_date_floor(), naive(2017, 12, 1));
+        assert_eq!(date("2017-12").to_naive_date_ceil(), naive(2017, 12, 31));
+
+        // leap years
+        assert_eq!(date("2020-02").to_naive_date_ceil(), naive(2020, 2, 29));
+        assert_eq!(date("2019-02").to_naive_date_ceil(), naive(2019, 2, 28));
+        assert_eq!(date("1900-02").to_naive_date_ceil(), naive(1900, 2, 28));
+        assert_eq!(date("2000-02").to_naive_date_ceil(), naive(2000, 2, 29));
+
+        let day = date("2020-02-29");
+        assert_eq!(day.to_naive_date_floor(), naive(2020, 2, 29));
+        assert_eq!(day.to_naive_date_ceil(), naive(2020, 2, 29));
+
+        assert_eq!(PartialDate::from(naive(2020, 2, 29).unwrap()), day);
+    }
 }
@@ -0,0 +1,147 @@
+use futures_util::{stream, Stream, StreamExt as _, TryStreamExt as _};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    api_request::ApiError,
+    objects::{
+        AnimeItem, AnimeList, AnimeSingleList, ForumTopic, ForumTopics, MangaItem, MangaList,
+        MangaSingleList, Paging, Post, RankItem, RankingList, SeasonList, SingleAnimeItem,
+        SingleMangaItem, TopicDetail,
+    },
+    MalClient, API_URL,
+};
+
+/// A response holding one page of a list.
+pub(crate) trait Page: DeserializeOwned {
+    type Item;
+
+    /// Split the page into its items and the url of the next page.
+    fn into_parts(self) -> (Vec<Self::Item>, Option<String>);
+}
+
+/// Stream every item of a list, starting at `url` and lazily following `paging.next`.
+pub(crate) fn stream<'a, P>(
+    client: &'a MalClient,
+    url: Result<String, ApiError>,
+    is_auth: bool,
+) -> impl Stream<Item = Result<P::Item, ApiError>> + 'a
+where
+    P: Page + 'a,
+    P::Item: 'a,
+{
+    let pages = stream::try_unfold(Some(url), move |next| async move {
+        let Some(url) = next else {
+            return Ok::<_, ApiError>(None);
+        };
+
+        let page: P = client.api_request().get(url?, is_auth).await?;
+        let (items, next) = page.into_parts();
+
+        Ok(Some((items, next.map(|next| rebase_next(client, &next)))))
+    });
+
+    pages
+        .map_ok(|items| stream::iter(items.into_iter().map(Ok)))
+        .try_flatten()
+}
+
+/// The url of the next page, on the client's api root.
+///
+/// mal gives it on its own root, which a client with another
+/// [base url](crate::MalClientBuilder::base_url) must not go around. Urls on any other root are refused,
+/// so the access token isn't sent there.
+fn rebase_next(client: &MalClient, next: &str) -> Result<String, ApiError> {
+    [client.api_url(""), API_URL.to_owned()]
+        .iter()
+        .find_map(|root| {
+            next.strip_prefix(root.as_str())
+                .filter(|rest| rest.starts_with(['/', '?']))
+        })
+        .map(|rest| client.api_url(rest))
+        .ok_or_else(|| ApiError::InvalidNextPage(next.to_owned()))
+}
+
+/// Collect the stream, stopping early once `max` items were received.
+pub(crate) async fn collect_all<T>(
+    stream: impl Stream<Item = Result<T, ApiError>>,
+    max: Option<usize>,
+) -> Result<Vec<T>, ApiError> {
+    stream.take(max.unwrap_or(usize::MAX)).try_collect().await
+}
+
+/// Turn the stream into an iterator which blocks for every page.
+#[cfg(feature = "blocking")]
+pub(crate) fn blocking_iter<T>(
+    stream: impl Stream<Item = Result<T, ApiError>>,
+) -> impl Iterator<Item = Result<T, ApiError>> {
+    let mut stream = Box::pin(stream);
+    std::iter::from_fn(move || crate::RUNTIME.block_on(stream.next()))
+}
+
+fn next_url(paging: Option<Paging>) -> Option<String> {
+    paging.and_then(|paging| paging.next)
+}
+
+impl Page for AnimeList {
+    type Item = AnimeItem;
+
+    fn into_parts(self) -> (Vec<Self::Item>, Option<String>) {
+        (self.data, next_url(self.paging))
+    }
+}
+
+impl Page for MangaList {
+    type Item = MangaItem;
+
+    fn into_parts(self) -> (Vec<Self::Item>, Option<String>) {
+        (self.data, next_url(self.paging))
+    }
+}
+
+impl Page for AnimeSingleList {
+    type Item = SingleAnimeItem;
+
+    fn into_parts(self) -> (Vec<Self::Item>, Option<String>) {
+        (self.data, next_url(self.paging))
+    }
+}
+
+impl Page for MangaSingleList {
+    type Item = SingleMangaItem;
+
+    fn into_parts(self) -> (Vec<Self::Item>, Option<String>) {
+        (self.data, next_url(self.paging))
+    }
+}
+
+impl<N: DeserializeOwned> Page for RankingList<N> {
+    type Item = RankItem<N>;
+
+    fn into_parts(self) -> (Vec<Self::Item>, Option<String>) {
+        (self.data, next_url(self.paging))
+    }
+}
+
+impl Page for SeasonList {
+    type Item = SingleAnimeItem;
+
+    fn into_parts(self) -> (Vec<Self::Item>, Option<String>) {
+        (self.data, next_url(self.paging))
+    }
+}
+
+impl Page for ForumTopics {
+    type Item = ForumTopic;
+
+    fn into_parts(self) -> (Vec<Self::Item>, Option<String>) {
+        (self.data, self.paging.next)
+    }
+}
+
+impl Page for TopicDetail {
+    type Item = Post;
+
+    fn into_parts(self) -> (Vec<Self::Item>, Option<String>) {
+        (self.data.posts, self.paging.next)
+    }
+}
@@ -0,0 +1,495 @@
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::future::BoxFuture;
+use tokio::time::Instant;
+use tower::{Layer, Service, ServiceExt as _};
+
+use crate::service::MalRequest;
+
+/// Which credentials a request is sent with. Each has its own rate limit bucket.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AuthMode {
+    /// The `X-MAL-CLIENT-ID` header.
+    ClientId,
+    /// The user's oauth2 access token.
+    Bearer,
+}
+
+impl AuthMode {
+    pub(crate) fn new(is_auth: bool) -> Self {
+        if is_auth {
+            Self::Bearer
+        } else {
+            Self::ClientId
+        }
+    }
+}
+
+/// How many requests may be sent in a period of time.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    requests: u32,
+    per: Duration,
+    burst: u32,
+}
+
+impl Default for RateLimit {
+    /// 1 request per second, as recommended for the mal api.
+    fn default() -> Self {
+        Self::new(1, Duration::from_secs(1))
+    }
+}
+
+impl RateLimit {
+    /// Allow `requests` requests every `per`.
+    ///
+    /// A `per` too short to space out `requests` requests (e.g. zero) is raised to a nanosecond per request.
+    pub fn new(requests: u32, per: Duration) -> Self {
+        let requests = requests.max(1);
+
+        Self {
+            requests,
+            per,
+            burst: 1,
+        }
+    }
+
+    /// Allow `requests` requests every second.
+    pub fn per_second(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(1))
+    }
+
+    /// How many requests may be sent at once after the client was idle.
+    ///
+    /// Default: 1
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// Time it takes for one request to become available again.
+    fn interval(&self) -> Duration {
+        // a zero interval would refill the bucket with infinitely many tokens
+        (self.per / self.requests).max(Duration::from_nanos(1))
+    }
+}
+
+/// A token bucket rate limiter shared by every request a [MalClient](crate::MalClient) sends.
+///
+/// Requests using the client id and requests using an access token are limited separately.
+///
+/// This is cheap to clone, and clones share the same buckets. Pass one to
+/// [`MalClientBuilder::rate_limiter()`](crate::MalClientBuilder::rate_limiter) for several clients
+/// to share a limit.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    client_id: Arc<Mutex<Bucket>>,
+    bearer: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            client_id: Arc::new(Mutex::new(Bucket::new(limit))),
+            bearer: Arc::new(Mutex::new(Bucket::new(limit))),
+        }
+    }
+
+    /// How long a request sent now with `mode` would have to wait.
+    pub fn current_wait(&self, mode: AuthMode) -> Duration {
+        let mut bucket = self.bucket(mode).lock().unwrap();
+        bucket.refill(Instant::now());
+        bucket.wait_for(1.0)
+    }
+
+    /// Wait until a request with `mode` may be sent.
+    pub(crate) async fn acquire(&self, mode: AuthMode) {
+        let bucket = self.bucket(mode);
+
+        let wait = {
+            let mut bucket = bucket.lock().unwrap();
+            bucket.refill(Instant::now());
+
+            // take the token now, even if it's not available yet. this reserves our spot so
+            // requests are let through in the order they arrived
+            bucket.tokens -= 1.0;
+            bucket.wait_for(0.0)
+        };
+
+        if wait.is_zero() {
+            return;
+        }
+
+        // if the request is cancelled while waiting, its token is given back
+        let mut reservation = Reservation {
+            bucket: Some(bucket),
+            turn: Instant::now() + wait,
+        };
+
+        tokio::time::sleep(wait).await;
+
+        reservation.bucket = None;
+    }
+
+    fn bucket(&self, mode: AuthMode) -> &Mutex<Bucket> {
+        match mode {
+            AuthMode::ClientId => &self.client_id,
+            AuthMode::Bearer => &self.bearer,
+        }
+    }
+}
+
+/// A [tower] layer which waits for a [RateLimiter] before every attempt at a request.
+///
+/// This is the client's [rate limit](crate::MalClientBuilder::rate_limit) as a layer, to order it among
+/// other layers with [`MalClientBuilder::layer()`](crate::MalClientBuilder::layer). Unlike the client's,
+/// it also limits requests replayed from a [Cassette](crate::cassette::Cassette).
+///
+/// ```rust,ignore
+/// MalClientBuilder::new().layer(
+///     ServiceBuilder::new()
+///         .layer(RetryLayer::new(RetryPolicy::default()))
+///         .layer(RateLimitLayer::new(RateLimiter::new(RateLimit::per_second(1))))
+///         .timeout(Duration::from_secs(10)),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    limiter: RateLimiter,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: RateLimiter) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+/// The service made by a [RateLimitLayer].
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limiter: RateLimiter,
+}
+
+impl<S> Service<MalRequest> for RateLimitService<S>
+where
+    S: Service<MalRequest> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // the inner service is only needed once the request is let through
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: MalRequest) -> Self::Future {
+        let clone = self.inner.clone();
+        let inner = mem::replace(&mut self.inner, clone);
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            limiter.acquire(request.auth).await;
+            inner.oneshot(request).await
+        })
+    }
+}
+
+/// A token taken from a bucket by a request which is still waiting to be sent.
+struct Reservation<'a> {
+    // none once the request was let through
+    bucket: Option<&'a Mutex<Bucket>>,
+    // when the request would have been let through
+    turn: Instant,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let Some(bucket) = self.bucket else {
+            return;
+        };
+
+        let now = Instant::now();
+        let mut bucket = bucket.lock().unwrap();
+        bucket.refill(now);
+
+        // requests queued behind this one already sleep until their turn, and a refunded token would let
+        // the next request through at the same time as the last of them. only the last turn can be given back
+        let last_turn = now + bucket.wait_for(0.0);
+        if last_turn <= self.turn + Duration::from_micros(1) {
+            bucket.tokens = (bucket.tokens + 1.0).min(bucket.limit.burst as f64);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    // may go negative when requests are waiting for their token
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let new_tokens = elapsed.as_secs_f64() / self.limit.interval().as_secs_f64();
+
+        self.tokens = (self.tokens + new_tokens).min(self.limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// How long until the bucket holds at least `tokens` tokens.
+    fn wait_for(&self, tokens: f64) -> Duration {
+        let missing = tokens - self.tokens;
+
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            self.limit.interval().mul_f64(missing)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Acquire a token and return how long it took.
+    async fn acquire(limiter: &RateLimiter, mode: AuthMode) -> Duration {
+        let start = Instant::now();
+        limiter.acquire(mode).await;
+        start.elapsed()
+    }
+
+    fn assert_about(actual: Duration, expected: Duration) {
+        let diff = actual.abs_diff(expected);
+        assert!(
+            diff < Duration::from_millis(1),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spaces_out_requests() {
+        let limiter = RateLimiter::new(RateLimit::per_second(2));
+
+        assert_eq!(acquire(&limiter, AuthMode::Bearer).await, Duration::ZERO);
+        assert_about(
+            acquire(&limiter, AuthMode::Bearer).await,
+            Duration::from_millis(500),
+        );
+        assert_about(
+            acquire(&limiter, AuthMode::Bearer).await,
+            Duration::from_millis(500),
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refills_up_to_burst() {
+        let limiter = RateLimiter::new(RateLimit::per_second(2).burst(3));
+
+        for _ in 0..3 {
+            assert_eq!(acquire(&limiter, AuthMode::Bearer).await, Duration::ZERO);
+        }
+        assert_about(
+            acquire(&limiter, AuthMode::Bearer).await,
+            Duration::from_millis(500),
+        );
+
+        // idling longer than it takes to refill doesn't save up more than the burst
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(limiter.current_wait(AuthMode::Bearer), Duration::ZERO);
+
+        for _ in 0..3 {
+            assert_eq!(acquire(&limiter, AuthMode::Bearer).await, Duration::ZERO);
+        }
+        assert_about(
+            limiter.current_wait(AuthMode::Bearer),
+            Duration::from_millis(500),
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn client_id_and_bearer_are_limited_separately() {
+        let limiter = RateLimiter::new(RateLimit::per_second(1));
+
+        limiter.acquire(AuthMode::ClientId).await;
+        assert_about(
+            limiter.current_wait(AuthMode::ClientId),
+            Duration::from_secs(1),
+        );
+        assert_eq!(limiter.current_wait(AuthMode::Bearer), Duration::ZERO);
+
+        assert_eq!(acquire(&limiter, AuthMode::Bearer).await, Duration::ZERO);
+
+        // clones share the buckets
+        let clone = limiter.clone();
+        assert_about(clone.current_wait(AuthMode::Bearer), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_request_gives_its_token_back() {
+        let limiter = RateLimiter::new(RateLimit::per_second(2));
+        limiter.acquire(AuthMode::Bearer).await;
+
+        // gives up after 100ms of its 500ms wait
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(100),
+            limiter.acquire(AuthMode::Bearer),
+        )
+        .await;
+        assert!(cancelled.is_err());
+
+        // without the refund, the next request would wait for the cancelled one's turn as well (900ms)
+        assert_about(
+            limiter.current_wait(AuthMode::Bearer),
+            Duration::from_millis(400),
+        );
+        assert_about(
+            acquire(&limiter, AuthMode::Bearer).await,
+            Duration::from_millis(400),
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_request_with_others_queued_behind_it() {
+        let limiter = RateLimiter::new(RateLimit::per_second(2));
+        limiter.acquire(AuthMode::Bearer).await;
+
+        let start = Instant::now();
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(100),
+            limiter.acquire(AuthMode::Bearer),
+        );
+        let queued = acquire(&limiter, AuthMode::Bearer);
+        let later = async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            limiter.acquire(AuthMode::Bearer).await;
+            start.elapsed()
+        };
+
+        let (cancelled, queued, later) =
+            futures_util::future::join3(cancelled, queued, later).await;
+        assert!(cancelled.is_err());
+
+        // the queued request keeps its turn, and the one after it isn't let through at the same time
+        assert_about(queued, Duration::from_secs(1));
+        assert_about(later, Duration::from_millis(1500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refund_is_capped_at_burst() {
+        let limiter = RateLimiter::new(RateLimit::per_second(2));
+        limiter.acquire(AuthMode::Bearer).await;
+
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(100),
+            limiter.acquire(AuthMode::Bearer),
+        )
+        .await;
+        assert!(cancelled.is_err());
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(acquire(&limiter, AuthMode::Bearer).await, Duration::ZERO);
+        assert_about(
+            limiter.current_wait(AuthMode::Bearer),
+            Duration::from_millis(500),
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn layer_limits_each_mode() {
+        use std::convert::Infallible;
+
+        use reqwest::{header::HeaderMap, Method, Url};
+
+        use crate::transport::HttpRequest;
+
+        let start = Instant::now();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+
+        let service = RateLimitLayer::new(RateLimiter::new(RateLimit::per_second(2))).layer(
+            tower::service_fn({
+                let sent = sent.clone();
+                move |request: MalRequest| {
+                    let sent = sent.clone();
+                    async move {
+                        sent.lock().unwrap().push((request.auth, start.elapsed()));
+                        Ok::<_, Infallible>(())
+                    }
+                }
+            }),
+        );
+
+        let request = |auth| MalRequest {
+            http: HttpRequest {
+                method: Method::GET,
+                url: Url::parse("https://api.myanimelist.net/v2/anime/21").unwrap(),
+                headers: HeaderMap::new(),
+                body: None,
+            },
+            auth,
+            attempt: 1,
+        };
+
+        for auth in [
+            AuthMode::Bearer,
+            AuthMode::Bearer,
+            AuthMode::ClientId,
+            AuthMode::Bearer,
+        ] {
+            service.clone().oneshot(request(auth)).await.unwrap();
+        }
+
+        let sent = sent.lock().unwrap();
+        let expected = [
+            (AuthMode::Bearer, Duration::ZERO),
+            (AuthMode::Bearer, Duration::from_millis(500)),
+            // the client id bucket is still full
+            (AuthMode::ClientId, Duration::from_millis(500)),
+            (AuthMode::Bearer, Duration::from_millis(1000)),
+        ];
+
+        for ((mode, at), (expected_mode, expected_at)) in sent.iter().zip(expected) {
+            assert_eq!(*mode, expected_mode);
+            assert_about(*at, expected_at);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_period_doesnt_wait() {
+        let limiter = RateLimiter::new(RateLimit::new(5, Duration::ZERO));
+
+        // at most a tick of tokio's timer
+        for _ in 0..100 {
+            assert!(acquire(&limiter, AuthMode::Bearer).await <= Duration::from_millis(1));
+        }
+    }
+}
@@ -0,0 +1,717 @@
+use std::{
+    mem,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode,
+};
+use tower::{Layer, Service, ServiceExt as _};
+use tracing::debug;
+
+use crate::{
+    api_request::ApiError,
+    service::{self, MalRequest},
+    transport::{BoxError, HttpResponse, TransportError},
+};
+
+/// Decides if and when a failed request is tried again.
+///
+/// By default, GET requests are retried up to 3 times on connection failures, timeouts,
+/// `429 Too Many Requests` and `500`/`502`/`503`/`504` responses, with an exponential backoff.
+///
+/// Requests which modify a user's list (PUT/DELETE) are only retried if
+/// [RetryPolicy::retry_non_idempotent] is set, since mal may have already applied them.
+///
+/// ```rust,ignore
+/// let policy = RetryPolicy::default()
+///     .max_attempts(5)
+///     .base_delay(Duration::from_secs(1));
+///
+/// MalClientBuilder::new().retry_policy(policy);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    statuses: Vec<StatusCode>,
+    retry_connection_errors: bool,
+    respect_retry_after: bool,
+    retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            statuses: vec![
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            retry_connection_errors: true,
+            respect_retry_after: true,
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy which never retries.
+    pub fn none() -> Self {
+        Self::default().max_attempts(1)
+    }
+
+    /// How many times a request is attempted in total, including the first attempt.
+    ///
+    /// Default: 3
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// The delay before the first retry. Every following retry doubles it.
+    ///
+    /// Default: 500ms
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    /// The longest the client will wait between attempts, including delays asked for by `Retry-After`.
+    ///
+    /// Default: 30s
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Randomize each delay to between half and all of it, so that many clients don't retry in lockstep.
+    ///
+    /// Default: true
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// The response statuses which are retried.
+    ///
+    /// Default: 429, 500, 502, 503, 504
+    pub fn statuses(mut self, statuses: impl IntoIterator<Item = StatusCode>) -> Self {
+        self.statuses = statuses.into_iter().collect();
+        self
+    }
+
+    /// Whether to retry when the request couldn't be sent (connection refused/reset, timeouts).
+    ///
+    /// Default: true
+    pub fn retry_connection_errors(mut self, retry: bool) -> Self {
+        self.retry_connection_errors = retry;
+        self
+    }
+
+    /// Wait for as long as the `Retry-After` header asks, when mal sends one.
+    ///
+    /// Default: true
+    pub fn respect_retry_after(mut self, respect: bool) -> Self {
+        self.respect_retry_after = respect;
+        self
+    }
+
+    /// Also retry the user list PUT/DELETE requests.
+    ///
+    /// Default: false
+    pub fn retry_non_idempotent(mut self, retry: bool) -> Self {
+        self.retry_non_idempotent = retry;
+        self
+    }
+
+    /// The delay before retrying a request which got a `status` response,
+    /// or `None` if it shouldn't be retried. `attempt` is the attempt which just failed, starting at 1.
+    pub(crate) fn delay_for_status(
+        &self,
+        attempt: u32,
+        idempotent: bool,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Option<Duration> {
+        if !self.can_retry(attempt, idempotent) || !self.statuses.contains(&status) {
+            return None;
+        }
+
+        let retry_after = self
+            .respect_retry_after
+            .then(|| retry_after(headers))
+            .flatten();
+
+        match retry_after {
+            Some(delay) => Some(delay.min(self.max_delay)),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    /// The delay before retrying a request which failed to send,
+    /// or `None` if it shouldn't be retried. `attempt` is the attempt which just failed, starting at 1.
+    pub(crate) fn delay_for_error(
+        &self,
+        attempt: u32,
+        idempotent: bool,
+        error: &TransportError,
+    ) -> Option<Duration> {
+        if !self.can_retry(attempt, idempotent)
+            || !self.retry_connection_errors
+            || !error.is_connection_error()
+        {
+            return None;
+        }
+
+        Some(self.backoff(attempt))
+    }
+
+    fn can_retry(&self, attempt: u32, idempotent: bool) -> bool {
+        attempt < self.max_attempts && (idempotent || self.retry_non_idempotent)
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+
+        if self.jitter {
+            delay.mul_f64(rand::random_range(0.5..=1.0))
+        } else {
+            delay
+        }
+    }
+}
+
+/// A [tower] layer which retries requests according to a [RetryPolicy].
+///
+/// This is the client's [retry policy](crate::MalClientBuilder::retry_policy) as a layer, to order it
+/// among other layers with [`MalClientBuilder::layer()`](crate::MalClientBuilder::layer), e.g. outside of
+/// a [RateLimitLayer](crate::rate_limit::RateLimitLayer) so every attempt is limited. Set the client's own
+/// policy to [RetryPolicy::none()] when using it, or requests are retried by both.
+///
+/// Errors of the layers inside it are converted like a [MalService](crate::service::MalService) does,
+/// so it retries an [ApiError::Transport] (including timeouts) and responses with a retried status.
+/// A `401` is left to the client, which refreshes the access token.
+#[derive(Debug, Clone)]
+pub struct RetryLayer {
+    policy: RetryPolicy,
+}
+
+impl RetryLayer {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self { policy }
+    }
+}
+
+impl<S> Layer<S> for RetryLayer {
+    type Service = RetryService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RetryService {
+            inner,
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+/// The service made by a [RetryLayer].
+#[derive(Debug, Clone)]
+pub struct RetryService<S> {
+    inner: S,
+    policy: RetryPolicy,
+}
+
+impl<S> Service<MalRequest> for RetryService<S>
+where
+    S: Service<MalRequest, Response = HttpResponse> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+{
+    type Response = HttpResponse;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<HttpResponse, ApiError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // every attempt waits for the inner service to be ready
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut request: MalRequest) -> Self::Future {
+        let clone = self.inner.clone();
+        let inner = mem::replace(&mut self.inner, clone);
+        let policy = self.policy.clone();
+
+        Box::pin(async move {
+            // Like the client, only GETs are safe to resend; PUT/DELETE modify the user's list.
+            let idempotent = request.http.method.is_safe();
+
+            loop {
+                let attempt = request.attempt;
+                let result = inner
+                    .clone()
+                    .oneshot(request.clone())
+                    .await
+                    .map_err(service::into_api_error);
+
+                let delay = match &result {
+                    Ok(response) => policy.delay_for_status(
+                        attempt,
+                        idempotent,
+                        response.status,
+                        &response.headers,
+                    ),
+                    Err(ApiError::Transport(e)) => policy.delay_for_error(attempt, idempotent, e),
+                    Err(_) => None,
+                };
+
+                let Some(delay) = delay else {
+                    return result;
+                };
+
+                debug!(attempt, ?delay, "retrying mal request");
+
+                tokio::time::sleep(delay).await;
+                request.attempt += 1;
+            }
+        })
+    }
+}
+
+/// Parse a `Retry-After` header, which is either a number of seconds or an http date.
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    fn headers(retry_after: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(retry_after).unwrap());
+        headers
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::default().jitter(false)
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_delay() {
+        let policy = policy()
+            .max_attempts(10)
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(500));
+
+        let delays = (1..=5)
+            .map(|attempt| policy.backoff(attempt))
+            .collect::<Vec<_>>();
+
+        assert_eq!(delays, [100, 200, 400, 500, 500].map(Duration::from_millis));
+
+        // a huge attempt doesn't overflow
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn jitter_stays_within_half_the_delay() {
+        let policy = RetryPolicy::default().base_delay(Duration::from_millis(100));
+
+        for _ in 0..100 {
+            let delay = policy.backoff(1);
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        let policy = policy();
+        let none = HeaderMap::new();
+
+        for status in [429, 500, 502, 503, 504] {
+            let status = StatusCode::from_u16(status).unwrap();
+            assert!(
+                policy.delay_for_status(1, true, status, &none).is_some(),
+                "{status}"
+            );
+        }
+
+        for status in [400, 401, 403, 404, 501] {
+            let status = StatusCode::from_u16(status).unwrap();
+            assert!(
+                policy.delay_for_status(1, true, status, &none).is_none(),
+                "{status}"
+            );
+        }
+
+        let policy = policy.statuses([StatusCode::NOT_FOUND]);
+        assert!(policy
+            .delay_for_status(1, true, StatusCode::NOT_FOUND, &none)
+            .is_some());
+        assert!(policy
+            .delay_for_status(1, true, StatusCode::SERVICE_UNAVAILABLE, &none)
+            .is_none());
+    }
+
+    #[test]
+    fn attempts_and_idempotency() {
+        let policy = policy().max_attempts(3);
+        let none = HeaderMap::new();
+        let status = StatusCode::SERVICE_UNAVAILABLE;
+
+        assert!(policy.delay_for_status(2, true, status, &none).is_some());
+        assert!(policy.delay_for_status(3, true, status, &none).is_none());
+
+        assert!(policy.delay_for_status(1, false, status, &none).is_none());
+        assert!(policy
+            .retry_non_idempotent(true)
+            .delay_for_status(1, false, status, &none)
+            .is_some());
+
+        assert!(RetryPolicy::none()
+            .delay_for_status(1, true, status, &none)
+            .is_none());
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let policy = policy().max_delay(Duration::from_secs(10));
+        let status = StatusCode::TOO_MANY_REQUESTS;
+
+        assert_eq!(
+            policy.delay_for_status(1, true, status, &headers("3")),
+            Some(Duration::from_secs(3))
+        );
+
+        // capped by the max delay
+        assert_eq!(
+            policy.delay_for_status(1, true, status, &headers("3600")),
+            Some(Duration::from_secs(10))
+        );
+
+        // an invalid header falls back to the backoff
+        assert_eq!(
+            policy.delay_for_status(1, true, status, &headers("soon")),
+            Some(policy.backoff(1))
+        );
+
+        assert_eq!(
+            policy
+                .respect_retry_after(false)
+                .delay_for_status(1, true, status, &headers("3")),
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        let date = (Utc::now() + chrono::Duration::seconds(120)).to_rfc2822();
+        let delay = retry_after(&headers(&date)).unwrap();
+        assert!(delay > Duration::from_secs(110) && delay <= Duration::from_secs(120));
+
+        // a date in the past means don't wait
+        let past = (Utc::now() - chrono::Duration::seconds(120)).to_rfc2822();
+        assert_eq!(retry_after(&headers(&past)), None);
+    }
+
+    mod layer {
+        use std::sync::{Arc, Mutex};
+
+        use reqwest::{Method, Url};
+
+        use super::*;
+        use crate::{
+            rate_limit::AuthMode,
+            transport::{BoxError, HttpRequest},
+        };
+
+        fn request(method: Method) -> MalRequest {
+            MalRequest {
+                http: HttpRequest {
+                    method,
+                    url: Url::parse("https://api.myanimelist.net/v2/anime/21").unwrap(),
+                    headers: HeaderMap::new(),
+                    body: None,
+                },
+                auth: AuthMode::ClientId,
+                attempt: 1,
+            }
+        }
+
+        fn response(status: StatusCode) -> Result<HttpResponse, BoxError> {
+            Ok(HttpResponse {
+                status,
+                headers: HeaderMap::new(),
+                body: Vec::new(),
+            })
+        }
+
+        /// Send `request` through a [RetryLayer] over a service answering with `results` in order,
+        /// and return the final result and the attempts the service saw.
+        async fn send(
+            policy: RetryPolicy,
+            request: MalRequest,
+            results: Vec<Result<HttpResponse, BoxError>>,
+        ) -> (Result<HttpResponse, ApiError>, Vec<u32>) {
+            let results = Arc::new(Mutex::new(results.into_iter()));
+            let attempts = Arc::new(Mutex::new(Vec::new()));
+
+            let service = RetryLayer::new(policy.jitter(false)).layer(tower::service_fn({
+                let attempts = attempts.clone();
+                move |request: MalRequest| {
+                    attempts.lock().unwrap().push(request.attempt);
+                    let result = results.lock().unwrap().next().unwrap();
+                    async move { result }
+                }
+            }));
+
+            let result = service.oneshot(request).await;
+            let attempts = attempts.lock().unwrap().clone();
+
+            (result, attempts)
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn retries_statuses_with_backoff() {
+            let start = tokio::time::Instant::now();
+
+            let (result, attempts) = send(
+                RetryPolicy::default(),
+                request(Method::GET),
+                vec![
+                    response(StatusCode::SERVICE_UNAVAILABLE),
+                    response(StatusCode::TOO_MANY_REQUESTS),
+                    response(StatusCode::OK),
+                ],
+            )
+            .await;
+
+            assert_eq!(result.unwrap().status, StatusCode::OK);
+            assert_eq!(attempts, [1, 2, 3]);
+            // 500ms, then 1s
+            assert_eq!(start.elapsed(), Duration::from_millis(1500));
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn retries_connection_errors_and_timeouts() {
+            let (result, attempts) = send(
+                RetryPolicy::default(),
+                request(Method::GET),
+                vec![
+                    Err(TransportError::Connection("reset".into()).into()),
+                    Err(tower::timeout::error::Elapsed::new().into()),
+                    response(StatusCode::OK),
+                ],
+            )
+            .await;
+
+            assert!(result.is_ok());
+            assert_eq!(attempts, [1, 2, 3]);
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn returns_the_last_failure() {
+            let (result, attempts) = send(
+                RetryPolicy::default().max_attempts(2),
+                request(Method::GET),
+                vec![
+                    response(StatusCode::SERVICE_UNAVAILABLE),
+                    response(StatusCode::BAD_GATEWAY),
+                ],
+            )
+            .await;
+
+            assert_eq!(result.unwrap().status, StatusCode::BAD_GATEWAY);
+            assert_eq!(attempts, [1, 2]);
+
+            let (result, attempts) = send(
+                RetryPolicy::default(),
+                request(Method::GET),
+                vec![Err("not retried".into())],
+            )
+            .await;
+
+            assert!(matches!(
+                result,
+                Err(ApiError::Transport(TransportError::Other(_)))
+            ));
+            assert_eq!(attempts, [1]);
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn leaves_put_and_unauthorized_alone() {
+            let (result, attempts) = send(
+                RetryPolicy::default(),
+                request(Method::PUT),
+                vec![response(StatusCode::SERVICE_UNAVAILABLE)],
+            )
+            .await;
+
+            assert_eq!(result.unwrap().status, StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(attempts, [1]);
+
+            let (result, attempts) = send(
+                RetryPolicy::default(),
+                request(Method::GET),
+                vec![response(StatusCode::UNAUTHORIZED)],
+            )
+            .await;
+
+            assert_eq!(result.unwrap().status, StatusCode::UNAUTHORIZED);
+            assert_eq!(attempts, [1]);
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    mod with_server {
+        use std::time::Instant;
+
+        use super::*;
+        use crate::{objects::WatchStatus, testing::MockServer, MalClient};
+
+        fn client(server: &MockServer, policy: RetryPolicy) -> MalClient {
+            server
+                .client_builder()
+                .retry_policy(policy.base_delay(Duration::from_millis(1)))
+                .build()
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn retries_until_success() {
+            let server = MockServer::start().await.unwrap();
+            let client = client(&server, RetryPolicy::default());
+
+            server.fail_next(StatusCode::SERVICE_UNAVAILABLE, None);
+            server.fail_next(StatusCode::BAD_GATEWAY, None);
+
+            let anime = client.anime().get().details().anime_id(5114).send().await;
+            assert!(anime.is_ok());
+            assert_eq!(server.request_count(), 3);
+        }
+
+        #[tokio::test]
+        async fn gives_up_after_max_attempts() {
+            let server = MockServer::start().await.unwrap();
+            let client = client(&server, RetryPolicy::default().max_attempts(2));
+
+            for _ in 0..3 {
+                server.fail_next(StatusCode::SERVICE_UNAVAILABLE, None);
+            }
+
+            let anime = client.anime().get().details().anime_id(5114).send().await;
+            assert_eq!(
+                anime.unwrap_err().status(),
+                Some(StatusCode::SERVICE_UNAVAILABLE)
+            );
+            assert_eq!(server.request_count(), 2);
+        }
+
+        #[tokio::test]
+        async fn other_statuses_are_not_retried() {
+            let server = MockServer::start().await.unwrap();
+            let client = client(&server, RetryPolicy::default());
+
+            server.fail_next(StatusCode::NOT_IMPLEMENTED, None);
+
+            let anime = client.anime().get().details().anime_id(5114).send().await;
+            assert_eq!(
+                anime.unwrap_err().status(),
+                Some(StatusCode::NOT_IMPLEMENTED)
+            );
+            assert_eq!(server.request_count(), 1);
+        }
+
+        #[tokio::test]
+        async fn waits_for_retry_after() {
+            let server = MockServer::start().await.unwrap();
+            let client = client(&server, RetryPolicy::default());
+
+            server.fail_next(StatusCode::TOO_MANY_REQUESTS, Some(1));
+
+            let start = Instant::now();
+            let anime = client.anime().get().details().anime_id(5114).send().await;
+
+            assert!(anime.is_ok());
+            assert!(start.elapsed() >= Duration::from_secs(1));
+            assert_eq!(server.request_count(), 2);
+        }
+
+        #[tokio::test]
+        async fn composes_with_other_layers() {
+            use tower::ServiceBuilder;
+
+            use crate::rate_limit::{RateLimit, RateLimitLayer, RateLimiter};
+
+            let server = MockServer::start().await.unwrap();
+            let client = server
+                .client_builder()
+                .retry_policy(RetryPolicy::none())
+                .layer(
+                    ServiceBuilder::new()
+                        .layer(RetryLayer::new(
+                            RetryPolicy::default().base_delay(Duration::from_millis(1)),
+                        ))
+                        .layer(RateLimitLayer::new(RateLimiter::new(
+                            RateLimit::per_second(1000),
+                        )))
+                        .timeout(Duration::from_secs(10)),
+                )
+                .build()
+                .unwrap();
+
+            server.fail_next(StatusCode::SERVICE_UNAVAILABLE, None);
+
+            let anime = client.anime().get().details().anime_id(5114).send().await;
+            assert!(anime.is_ok());
+            assert_eq!(server.request_count(), 2);
+        }
+
+        #[tokio::test]
+        async fn list_updates_are_not_retried_by_default() {
+            let server = MockServer::start().await.unwrap();
+
+            let update = |client: MalClient| async move {
+                client
+                    .user_animelist()
+                    .put()
+                    .anime_id(21)
+                    .status(WatchStatus::Watching)
+                    .send()
+                    .await
+            };
+
+            server.fail_next(StatusCode::SERVICE_UNAVAILABLE, None);
+            let result = update(client(&server, RetryPolicy::default())).await;
+            assert!(result.is_err());
+            assert_eq!(server.request_count(), 1);
+
+            server.fail_next(StatusCode::SERVICE_UNAVAILABLE, None);
+            let opted_in = client(&server, RetryPolicy::default().retry_non_idempotent(true));
+            assert!(update(opted_in).await.is_ok());
+            assert_eq!(server.request_count(), 3);
+        }
+    }
+}
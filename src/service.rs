@@ -0,0 +1,161 @@
+use std::{
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_util::future::BoxFuture;
+use tower::{
+    timeout::error::Elapsed,
+    util::{BoxCloneSyncService, ServiceExt},
+    Layer, Service,
+};
+
+use crate::{
+    api_request::ApiError,
+    cassette::{Cassette, CassetteMode, RecordedRequest},
+    rate_limit::AuthMode,
+    transport::{BoxError, HttpRequest, HttpResponse, HttpTransport, TransportError},
+};
+
+/// A request going through a [MalService].
+#[derive(Debug, Clone)]
+pub struct MalRequest {
+    /// The http request, with its credentials already set.
+    pub http: HttpRequest,
+    /// Which credentials the request is sent with.
+    pub auth: AuthMode,
+    /// Which attempt at the request this is, starting at 1.
+    ///
+    /// This goes up when the client or a [RetryLayer](crate::retry::RetryLayer) retries the request,
+    /// see [RetryPolicy](crate::retry::RetryPolicy).
+    pub attempt: u32,
+}
+
+/// The [tower] service a [MalClient](crate::MalClient) sends its requests through.
+///
+/// Requests reach the service after the client's retries, rate limiting and token refreshing were handled;
+/// the service sends each attempt once, to the client's [Cassette] or [HttpTransport].
+/// Wrap it in tower middleware with [`MalClientBuilder::layer()`](crate::MalClientBuilder::layer), including
+/// a [RetryLayer](crate::retry::RetryLayer) and [RateLimitLayer](crate::rate_limit::RateLimitLayer) to
+/// retry and limit requests among your other layers.
+///
+/// Errors a layer returns are converted to an [ApiError]. An [ApiError] or [TransportError] is returned
+/// as is, a timeout as a [TransportError::Connection] (which is retried), and anything else as a
+/// [TransportError::Other].
+#[derive(Clone)]
+pub struct MalService {
+    inner: BoxCloneSyncService<MalRequest, HttpResponse, ApiError>,
+}
+
+impl fmt::Debug for MalService {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MalService").finish_non_exhaustive()
+    }
+}
+
+impl MalService {
+    /// The innermost service, which sends the request to the cassette or transport.
+    pub(crate) fn new(transport: Arc<dyn HttpTransport>, cassette: Option<Arc<Cassette>>) -> Self {
+        Self {
+            inner: BoxCloneSyncService::new(Dispatch {
+                transport,
+                cassette,
+            }),
+        }
+    }
+
+    /// Wrap the service in `layer`.
+    pub fn layer<L>(self, layer: L) -> Self
+    where
+        L: Layer<MalService>,
+        L::Service: Service<MalRequest, Response = HttpResponse> + Clone + Send + Sync + 'static,
+        <L::Service as Service<MalRequest>>::Error: Into<BoxError>,
+        <L::Service as Service<MalRequest>>::Future: Send + 'static,
+    {
+        let service = layer.layer(self).map_err(into_api_error);
+
+        Self {
+            inner: BoxCloneSyncService::new(service),
+        }
+    }
+}
+
+impl Service<MalRequest> for MalService {
+    type Response = HttpResponse;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<HttpResponse, ApiError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: MalRequest) -> Self::Future {
+        self.inner.call(request)
+    }
+}
+
+/// Sends a request to mal, or replays it from the cassette.
+#[derive(Clone)]
+struct Dispatch {
+    transport: Arc<dyn HttpTransport>,
+    cassette: Option<Arc<Cassette>>,
+}
+
+impl Service<MalRequest> for Dispatch {
+    type Response = HttpResponse;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<HttpResponse, ApiError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: MalRequest) -> Self::Future {
+        let Self {
+            transport,
+            cassette,
+        } = self.clone();
+
+        Box::pin(async move {
+            let request = request.http;
+
+            let Some(cassette) = cassette else {
+                return Ok(transport.send(request).await?);
+            };
+
+            match cassette.mode() {
+                CassetteMode::Replay => Ok(cassette.play(&request).await?),
+                CassetteMode::Record => {
+                    let recorded = RecordedRequest::new(&request);
+                    let response = transport.send(request).await?;
+
+                    cassette.record_exchange(recorded, &response).await?;
+
+                    Ok(response)
+                }
+            }
+        })
+    }
+}
+
+/// Convert the error of a layer, see [MalService].
+pub(crate) fn into_api_error(error: impl Into<BoxError>) -> ApiError {
+    let error = error.into();
+
+    let error = match error.downcast::<ApiError>() {
+        Ok(error) => return *error,
+        Err(error) => error,
+    };
+
+    let error = match error.downcast::<TransportError>() {
+        Ok(error) => return ApiError::Transport(*error),
+        Err(error) => error,
+    };
+
+    if error.is::<Elapsed>() {
+        TransportError::Connection(error).into()
+    } else {
+        TransportError::Other(error).into()
+    }
+}
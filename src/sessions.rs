@@ -0,0 +1,401 @@
+//! Clients for many users, e.g. in a web app, which share one http client, rate limiter and cache.
+//!
+//! ```rust,ignore
+//! let sessions: MalSessions<u64> = MalClient::builder()
+//!     .client_id(client_id)
+//!     .auth(auth)
+//!     .rate_limit(RateLimit::new(10, Duration::from_secs(1)))
+//!     .build_sessions()?
+//!     .on_token_refresh(|user_id, tokens| save_tokens(*user_id, tokens));
+//!
+//! sessions.insert(user_id, tokens);
+//!
+//! if let Some(client) = sessions.for_user(&user_id) {
+//!     let list = client.user_animelist().get().user_name(Username::Me).send().await?;
+//! }
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::{
+    auth::{Auth, AuthTokens, SharedAuth},
+    MalClient, TokenRefreshCallback,
+};
+
+type SessionRefreshCallback<K> = Arc<dyn Fn(&K, &AuthTokens) + Send + Sync + 'static>;
+
+/// The sessions of many users, by user id.
+///
+/// Every session is a [MalClient] with its own tokens, which are refreshed independently of the other
+/// sessions' with a copy of the [Auth] passed to the builder. The http client, rate limiter, cache and other
+/// settings are shared by all sessions. Build one with
+/// [`MalClientBuilder::build_sessions()`](crate::MalClientBuilder::build_sessions).
+///
+/// Sessions which weren't used for [MalSessions::idle_timeout] are evicted. A client already handed out
+/// keeps working after its session was evicted.
+pub struct MalSessions<K> {
+    // the client every session's client is made from; its own tokens are never used
+    template: MalClient,
+    // cloned for each session, to refresh its tokens
+    auth: Option<Auth>,
+    on_token_refresh: Option<SessionRefreshCallback<K>>,
+    idle_timeout: Option<Duration>,
+    sessions: Mutex<HashMap<K, Session>>,
+}
+
+struct Session {
+    client: MalClient,
+    last_used: Instant,
+}
+
+impl<K> fmt::Debug for MalSessions<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MalSessions")
+            .field("template", &self.template)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("sessions", &self.sessions.lock().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K> MalSessions<K>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+{
+    pub(crate) fn new(template: MalClient, auth: Option<Auth>) -> Self {
+        Self {
+            template,
+            auth,
+            on_token_refresh: None,
+            idle_timeout: Some(Duration::from_secs(30 * 60)),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long a session may go without being used before it is evicted. `None` never evicts sessions.
+    ///
+    /// A session is used when it is inserted or looked up with [MalSessions::for_user].
+    ///
+    /// Default: 30 minutes
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Called with the user id and the new tokens every time a session refreshes its access token.
+    ///
+    /// Use this to persist the refreshed tokens. A callback set with
+    /// [`MalClientBuilder::on_token_refresh()`](crate::MalClientBuilder::on_token_refresh) is called as well.
+    pub fn on_token_refresh(
+        mut self,
+        cb: impl Fn(&K, &AuthTokens) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_token_refresh = Some(Arc::new(cb));
+        self
+    }
+
+    /// Start a session for `id` with `tokens`, and return its client.
+    ///
+    /// If `id` already has a session, it keeps its client and [SharedAuth], and only its tokens are
+    /// replaced. Clients handed out for it before use the new tokens as well.
+    ///
+    /// Sessions which are idle for too long are evicted.
+    pub fn insert(&self, id: K, tokens: impl Into<AuthTokens>) -> MalClient {
+        let tokens = tokens.into();
+        let now = Instant::now();
+
+        let mut sessions = self.sessions.lock().unwrap();
+        self.evict_expired(&mut sessions, now);
+
+        if let Some(session) = sessions.get_mut(&id) {
+            let client = &session.client;
+
+            client.set_tokens(tokens.clone());
+            if let Some(auth) = &client.auth {
+                auth.try_set_tokens(tokens);
+            }
+
+            session.last_used = now;
+            return client.clone();
+        }
+
+        let client = self.session_client(id.clone(), tokens);
+
+        sessions.insert(
+            id,
+            Session {
+                client: client.clone(),
+                last_used: now,
+            },
+        );
+
+        client
+    }
+
+    /// The client of `id`'s session, if it has one which isn't idle for too long.
+    ///
+    /// The client is cheap to clone, and shares its tokens with the session.
+    pub fn for_user(&self, id: &K) -> Option<MalClient> {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let session = sessions.get_mut(id)?;
+        if self.is_expired(session, now) {
+            sessions.remove(id);
+            return None;
+        }
+
+        session.last_used = now;
+        Some(session.client.clone())
+    }
+
+    /// The current tokens of `id`'s session. If the session refreshed its access token, these are the new tokens.
+    ///
+    /// This doesn't count as using the session.
+    pub fn tokens(&self, id: &K) -> Option<AuthTokens> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.get(id).map(|session| session.client.tokens())
+    }
+
+    /// End `id`'s session, e.g. when the user logs out, and return its last tokens.
+    pub fn remove(&self, id: &K) -> Option<AuthTokens> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.remove(id).map(|session| session.client.tokens())
+    }
+
+    /// Whether `id` has a session.
+    pub fn contains(&self, id: &K) -> bool {
+        self.sessions.lock().unwrap().contains_key(id)
+    }
+
+    /// The number of sessions, including idle ones which weren't evicted yet.
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Evict the sessions which are idle for too long now, and return their ids and last tokens.
+    ///
+    /// Idle sessions are also evicted whenever a session is inserted, call this to evict them sooner.
+    pub fn evict_idle(&self) -> Vec<(K, AuthTokens)> {
+        let Some(idle_timeout) = self.idle_timeout else {
+            return Vec::new();
+        };
+
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let expired = sessions
+            .iter()
+            .filter(|(_, session)| now.duration_since(session.last_used) >= idle_timeout)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .filter_map(|id| {
+                let session = sessions.remove(&id)?;
+                Some((id, session.client.tokens()))
+            })
+            .collect()
+    }
+
+    fn is_expired(&self, session: &Session, now: Instant) -> bool {
+        self.idle_timeout
+            .is_some_and(|timeout| now.duration_since(session.last_used) >= timeout)
+    }
+
+    fn evict_expired(&self, sessions: &mut HashMap<K, Session>, now: Instant) {
+        sessions.retain(|_, session| !self.is_expired(session, now));
+    }
+
+    /// A client for `id`, sharing everything but its tokens and the [Auth] refreshing them.
+    fn session_client(&self, id: K, tokens: AuthTokens) -> MalClient {
+        let shared_cb = self.template.on_token_refresh.clone();
+        let session_cb = self.on_token_refresh.clone();
+
+        let on_token_refresh: TokenRefreshCallback = Arc::new(move |tokens: &AuthTokens| {
+            if let Some(cb) = &shared_cb {
+                cb(tokens);
+            }
+
+            if let Some(cb) = &session_cb {
+                cb(&id, tokens);
+            }
+        });
+
+        let auth = self.auth.clone().map(|mut auth| {
+            auth.set_tokens_unchecked(tokens.clone());
+            SharedAuth::new(auth)
+        });
+
+        MalClient {
+            auth_tokens: Arc::new(RwLock::new(tokens)),
+            auth,
+            on_token_refresh: Some(on_token_refresh),
+            ..self.template.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oauth2::{AccessToken, RefreshToken};
+
+    use super::*;
+    use crate::ClientId;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn sessions() -> MalSessions<u32> {
+        MalClient::builder()
+            .client_id(ClientId::new("id".to_owned()))
+            .build_sessions()
+            .unwrap()
+    }
+
+    fn tokens(access_token: &str) -> AuthTokens {
+        AuthTokens {
+            access_token: AccessToken::new(access_token.to_owned()),
+            refresh_token: RefreshToken::new(format!("{access_token}-refresh")),
+            ..AuthTokens::default()
+        }
+    }
+
+    fn access_token(tokens: Option<AuthTokens>) -> Option<String> {
+        tokens.map(|tokens| tokens.access_token.secret().clone())
+    }
+
+    #[tokio::test]
+    async fn insert_replaces_the_tokens_of_a_session() {
+        let sessions = sessions();
+
+        let first = sessions.insert(1, tokens("first"));
+        sessions.insert(2, tokens("other"));
+        let again = sessions.insert(1, tokens("second"));
+
+        assert_eq!(sessions.len(), 2);
+        // the client handed out before uses the new tokens as well
+        assert_eq!(first.tokens().access_token.secret(), "second");
+        assert_eq!(again.tokens().access_token.secret(), "second");
+        assert_eq!(access_token(sessions.tokens(&1)).as_deref(), Some("second"));
+        assert_eq!(access_token(sessions.tokens(&2)).as_deref(), Some("other"));
+
+        assert_eq!(access_token(sessions.remove(&1)).as_deref(), Some("second"));
+        assert!(!sessions.contains(&1));
+        assert!(sessions.for_user(&1).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn evicts_sessions_idle_for_30_minutes() {
+        let sessions = sessions();
+        sessions.insert(1, tokens("first"));
+        sessions.insert(2, tokens("second"));
+
+        tokio::time::advance(20 * MINUTE).await;
+        assert!(sessions.for_user(&1).is_some());
+
+        // only the session which wasn't used since
+        tokio::time::advance(10 * MINUTE).await;
+        let evicted = sessions.evict_idle();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].0, 2);
+        assert_eq!(evicted[0].1.access_token.secret(), "second");
+        assert!(sessions.evict_idle().is_empty());
+
+        // looking up an idle session evicts it as well
+        tokio::time::advance(20 * MINUTE).await;
+        assert!(sessions.contains(&1));
+        assert!(sessions.for_user(&1).is_none());
+        assert!(sessions.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn insert_evicts_idle_sessions() {
+        let sessions = sessions();
+        sessions.insert(1, tokens("first"));
+        sessions.insert(2, tokens("second"));
+
+        tokio::time::advance(29 * MINUTE).await;
+        // inserting counts as using the session
+        sessions.insert(2, tokens("second"));
+
+        tokio::time::advance(MINUTE).await;
+        let client = sessions.insert(3, tokens("third"));
+
+        assert!(!sessions.contains(&1));
+        assert!(sessions.contains(&2));
+        assert_eq!(sessions.len(), 2);
+
+        // a client handed out keeps working after its session was evicted
+        tokio::time::advance(30 * MINUTE).await;
+        assert_eq!(sessions.evict_idle().len(), 2);
+        assert_eq!(client.tokens().access_token.secret(), "third");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_sessions_without_an_idle_timeout() {
+        let sessions = sessions().idle_timeout(None);
+        sessions.insert(1, tokens("first"));
+
+        tokio::time::advance(24 * 60 * MINUTE).await;
+        assert!(sessions.evict_idle().is_empty());
+        assert!(sessions.for_user(&1).is_some());
+    }
+
+    #[cfg(feature = "testing")]
+    mod with_server {
+        use super::*;
+        use crate::{objects::Username, testing::MockServer};
+
+        #[tokio::test]
+        async fn each_session_refreshes_its_own_tokens() {
+            let server = MockServer::start().await.unwrap();
+            let refreshed = Arc::new(Mutex::new(Vec::new()));
+
+            let sessions = server
+                .client_builder()
+                .auth(server.auth())
+                .build_sessions()
+                .unwrap()
+                .on_token_refresh({
+                    let refreshed = refreshed.clone();
+                    move |id: &u32, tokens: &AuthTokens| {
+                        refreshed
+                            .lock()
+                            .unwrap()
+                            .push((*id, tokens.access_token.secret().clone()));
+                    }
+                });
+
+            // a session started with stale tokens, which the user then logged in again for
+            let client = sessions.insert(1, tokens("stale"));
+            sessions.insert(1, server.tokens());
+
+            server.expire_access_token();
+
+            client
+                .user_animelist()
+                .get()
+                .user_name(Username::Me)
+                .send()
+                .await
+                .unwrap();
+
+            let current = server.tokens().access_token.secret().clone();
+            assert_eq!(*refreshed.lock().unwrap(), [(1, current.clone())]);
+            assert_eq!(access_token(sessions.tokens(&1)), Some(current));
+        }
+    }
+}
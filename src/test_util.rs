@@ -0,0 +1,35 @@
+//! Helpers shared by the unit tests.
+
+use std::{fs, path::PathBuf};
+
+/// A directory of its own in the system's temp directory, removed on drop.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new() -> Self {
+        let path =
+            std::env::temp_dir().join(format!("myanimelist-test-{:016x}", rand::random::<u64>()));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub(crate) fn path(&self) -> &PathBuf {
+        &self.0
+    }
+
+    /// The names of the files in the directory, sorted.
+    pub(crate) fn files(&self) -> Vec<String> {
+        let mut files = fs::read_dir(&self.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = fs::remove_dir_all(&self.0);
+    }
+}
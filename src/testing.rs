@@ -0,0 +1,1275 @@
+//! A local stand-in for the mal api, so a [MalClient] can be used without network access.
+//!
+//! ```rust,ignore
+//! let server = MockServer::start().await?;
+//! let client = server.client();
+//!
+//! let anime = client.anime().get().details().anime_id(5114).send().await?;
+//! ```
+//!
+//! The server is seeded from [Fixtures]. Changes made through the user list PUT/DELETE endpoints
+//! are kept for as long as the server runs, and can be inspected with [MockServer::animelist]
+//! and [MockServer::mangalist].
+//!
+//! [MockServer::fail_next] makes it fail requests, to test how the client retries them.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    io, mem,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::{
+    auth::{Auth, AuthTokens},
+    http_server::{self, Request, Response},
+    AccessToken, ClientId, ClientSecret, MalClient, MalClientBuilder, RedirectUrl, RefreshToken,
+};
+
+/// The client id the mock server accepts in the `X-MAL-CLIENT-ID` header.
+pub const MOCK_CLIENT_ID: &str = "mock-client-id";
+/// The client secret used by [MockServer::auth]. The server accepts any secret.
+pub const MOCK_CLIENT_SECRET: &str = "mock-client-secret";
+/// The access token the mock server accepts until it is refreshed.
+pub const MOCK_ACCESS_TOKEN: &str = "mock-access-token";
+/// The refresh token the mock server accepts until it is used.
+pub const MOCK_REFRESH_TOKEN: &str = "mock-refresh-token";
+
+const FIXTURES: &str = include_str!("testing/fixtures.json");
+
+// how long issued access tokens are valid for, in seconds
+const EXPIRES_IN: u64 = 3600;
+
+// fields mal returns for a node when they weren't asked for
+const NODE_FIELDS: &[&str] = &["id", "title", "main_picture"];
+const USER_FIELDS: &[&str] = &[
+    "id",
+    "name",
+    "picture",
+    "gender",
+    "birthday",
+    "location",
+    "joined_at",
+    "time_zone",
+    "is_supporter",
+];
+
+/// The data a [MockServer] serves.
+///
+/// Every value is the json mal would return for it with all fields requested.
+/// [Fixtures::default()] holds a small set of anime, manga, list entries and forum topics.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fixtures {
+    pub anime: Vec<Value>,
+    pub manga: Vec<Value>,
+    /// The user the access token belongs to.
+    pub user: Value,
+    /// The user's anime list status, by anime id.
+    pub animelist: BTreeMap<u64, Value>,
+    /// The user's manga list status, by manga id.
+    pub mangalist: BTreeMap<u64, Value>,
+    pub forum_boards: Value,
+    pub forum_topics: Vec<Value>,
+    /// Topic details (title, posts and poll), by topic id.
+    pub topics: BTreeMap<u64, Value>,
+}
+
+impl Default for Fixtures {
+    fn default() -> Self {
+        Self::from_json(FIXTURES).expect("bundled fixtures are valid")
+    }
+}
+
+impl Fixtures {
+    /// Parse fixtures in the same format as the bundled `fixtures.json`.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// A mal api server running on localhost.
+///
+/// It serves the v2 endpoints used by [MalClient], and the oauth2 token endpoint.
+/// The server stops when this is dropped.
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl MockServer {
+    /// Start a server with the default [Fixtures].
+    pub async fn start() -> io::Result<Self> {
+        Self::with_fixtures(Fixtures::default()).await
+    }
+
+    /// Start a server with the default [Fixtures].
+    #[cfg(feature = "blocking")]
+    pub fn start_blocking() -> io::Result<Self> {
+        crate::RUNTIME.block_on(Self::start())
+    }
+
+    /// Start a server serving `fixtures`.
+    pub async fn with_fixtures(fixtures: Fixtures) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let state = Arc::new(Mutex::new(State::new(fixtures)));
+        let task = tokio::spawn(serve(listener, state.clone()));
+
+        Ok(Self { addr, state, task })
+    }
+
+    /// The address the server listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The root of the api, for [MalClientBuilder::base_url].
+    pub fn api_url(&self) -> String {
+        format!("http://{}/v2", self.addr)
+    }
+
+    /// The root of the oauth2 endpoints, for [Auth::set_base_url].
+    ///
+    /// Only the token endpoint is served.
+    pub fn oauth_url(&self) -> String {
+        format!("http://{}/v1", self.addr)
+    }
+
+    /// An [Auth] using the server's oauth2 endpoints, holding the tokens it accepts.
+    ///
+    /// Give it to [MalClientBuilder::auth] to have the client refresh its tokens with the server.
+    pub fn auth(&self) -> Auth {
+        let mut auth = Auth::from_auth_tokens(
+            self.tokens(),
+            ClientId::new(MOCK_CLIENT_ID.to_owned()),
+            ClientSecret::new(MOCK_CLIENT_SECRET.to_owned()),
+            RedirectUrl::new("http://localhost/callback".to_owned()).unwrap(),
+        );
+
+        auth.set_base_url(&self.oauth_url())
+            .expect("server url is valid");
+
+        auth
+    }
+
+    /// A [MalClientBuilder] pointed at the server, with the client id and tokens it accepts.
+    pub fn client_builder(&self) -> MalClientBuilder {
+        MalClient::builder()
+            .base_url(self.api_url())
+            .client_id(ClientId::new(MOCK_CLIENT_ID.to_owned()))
+            .auth_tokens(self.tokens())
+            // a proxy from the environment can't reach our localhost
+            .http_builder(|builder| builder.no_proxy().build())
+    }
+
+    /// A [MalClient] pointed at the server. See [MockServer::client_builder].
+    pub fn client(&self) -> MalClient {
+        self.client_builder()
+            .build()
+            .expect("Failed building the MalClient")
+    }
+
+    /// The tokens the server currently accepts.
+    pub fn tokens(&self) -> AuthTokens {
+        self.state.lock().unwrap().tokens()
+    }
+
+    /// Reject the current access token, like mal does once it expired.
+    ///
+    /// Requests using it fail with a 401 until the tokens are refreshed.
+    pub fn expire_access_token(&self) {
+        self.state.lock().unwrap().access_valid = false;
+    }
+
+    /// The user's anime list status, by anime id.
+    pub fn animelist(&self) -> BTreeMap<u64, Value> {
+        self.state.lock().unwrap().fixtures.animelist.clone()
+    }
+
+    /// The user's manga list status, by manga id.
+    pub fn mangalist(&self) -> BTreeMap<u64, Value> {
+        self.state.lock().unwrap().fixtures.mangalist.clone()
+    }
+
+    /// How many requests the server answered.
+    pub fn request_count(&self) -> usize {
+        self.state.lock().unwrap().requests
+    }
+
+    /// Answer an upcoming api request with `status` instead, like mal's `503` when it is overloaded,
+    /// or a `429` with `retry_after` seconds in its `Retry-After` header.
+    ///
+    /// Every call queues one failure, which the following api requests use up in order.
+    pub fn fail_next(&self, status: StatusCode, retry_after: Option<u64>) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .push_back((status, retry_after));
+    }
+}
+
+async fn serve(listener: TcpListener, state: Arc<Mutex<State>>) {
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            let Ok(request) = http_server::read_request(&mut stream).await else {
+                return;
+            };
+
+            let response = state.lock().unwrap().handle(&request);
+            _ = http_server::write_response(&mut stream, response).await;
+        });
+    }
+}
+
+/// Who a request was sent as.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Caller {
+    ClientId,
+    User,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Media {
+    Anime,
+    Manga,
+}
+
+/// How a PUT to `my_list_status` maps onto a list entry.
+struct ListSpec {
+    statuses: &'static [&'static str],
+    default_status: &'static str,
+    /// Form parameter and the entry field it sets.
+    numbers: &'static [(&'static str, &'static str)],
+    flag: &'static str,
+}
+
+const ANIME_LIST: ListSpec = ListSpec {
+    statuses: &[
+        "watching",
+        "completed",
+        "on_hold",
+        "dropped",
+        "plan_to_watch",
+    ],
+    default_status: "plan_to_watch",
+    numbers: &[
+        ("score", "score"),
+        ("num_watched_episodes", "num_episodes_watched"),
+        ("priority", "priority"),
+        ("num_times_rewatched", "num_times_rewatched"),
+        ("rewatch_value", "rewatch_value"),
+    ],
+    flag: "is_rewatching",
+};
+
+const MANGA_LIST: ListSpec = ListSpec {
+    statuses: &["reading", "completed", "on_hold", "dropped", "plan_to_read"],
+    default_status: "plan_to_read",
+    numbers: &[
+        ("score", "score"),
+        ("num_volumes_read", "num_volumes_read"),
+        ("num_chapters_read", "num_chapters_read"),
+        ("priority", "priority"),
+        ("num_times_reread", "num_times_reread"),
+        ("reread_value", "reread_value"),
+    ],
+    flag: "is_rereading",
+};
+
+impl Media {
+    fn list_spec(self) -> &'static ListSpec {
+        match self {
+            Self::Anime => &ANIME_LIST,
+            Self::Manga => &MANGA_LIST,
+        }
+    }
+
+    /// The name used in `{media}_title` style sort parameters.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Anime => "anime",
+            Self::Manga => "manga",
+        }
+    }
+}
+
+type Handled = Result<Value, Response>;
+
+#[derive(Debug)]
+struct State {
+    fixtures: Fixtures,
+    access_token: String,
+    refresh_token: String,
+    access_valid: bool,
+    expires_at: u64,
+    // how many times tokens were issued
+    generation: u32,
+    requests: usize,
+    // queued with fail_next
+    failures: VecDeque<(StatusCode, Option<u64>)>,
+}
+
+impl State {
+    fn new(fixtures: Fixtures) -> Self {
+        Self {
+            fixtures,
+            access_token: MOCK_ACCESS_TOKEN.to_owned(),
+            refresh_token: MOCK_REFRESH_TOKEN.to_owned(),
+            access_valid: true,
+            expires_at: now() + EXPIRES_IN,
+            generation: 0,
+            requests: 0,
+            failures: VecDeque::new(),
+        }
+    }
+
+    fn tokens(&self) -> AuthTokens {
+        AuthTokens {
+            access_token: AccessToken::new(self.access_token.clone()),
+            refresh_token: RefreshToken::new(self.refresh_token.clone()),
+            expires_at: self.expires_at,
+            refresh_expires_at: now() + 31 * 24 * 60 * 60,
+        }
+    }
+
+    fn handle(&mut self, req: &Request) -> Response {
+        self.requests += 1;
+
+        let path = req.url.path().to_owned();
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+        let result = match (req.method.as_str(), segments.as_slice()) {
+            ("POST", ["v1", "oauth2", "token"]) => return self.token(req),
+            (method, ["v2", route @ ..]) => self.api(req, method, route),
+            _ => Err(not_found()),
+        };
+
+        match result {
+            Ok(body) => Response::json(StatusCode::OK, &body),
+            Err(response) => response,
+        }
+    }
+
+    fn api(&mut self, req: &Request, method: &str, route: &[&str]) -> Handled {
+        if let Some((status, retry_after)) = self.failures.pop_front() {
+            let response = error(status, "failed", "");
+            return Err(match retry_after {
+                Some(secs) => response.header("Retry-After", secs.to_string()),
+                None => response,
+            });
+        }
+
+        let caller = self.caller(req)?;
+
+        match (method, route) {
+            ("GET", ["anime"]) => self.search(req, caller, Media::Anime),
+            ("GET", ["anime", "ranking"]) => self.ranking(req, caller, Media::Anime),
+            ("GET", ["anime", "suggestions"]) => self.suggestions(req, caller),
+            ("GET", ["anime", "season", year, season]) => self.seasonal(req, caller, year, season),
+            ("GET", ["anime", id]) => self.details(req, caller, Media::Anime, id),
+            ("PUT", ["anime", id, "my_list_status"]) => {
+                self.put_list_status(req, caller, Media::Anime, id)
+            }
+            ("DELETE", ["anime", id, "my_list_status"]) => {
+                self.delete_list_status(caller, Media::Anime, id)
+            }
+
+            ("GET", ["manga"]) => self.search(req, caller, Media::Manga),
+            ("GET", ["manga", "ranking"]) => self.ranking(req, caller, Media::Manga),
+            ("GET", ["manga", id]) => self.details(req, caller, Media::Manga, id),
+            ("PUT", ["manga", id, "my_list_status"]) => {
+                self.put_list_status(req, caller, Media::Manga, id)
+            }
+            ("DELETE", ["manga", id, "my_list_status"]) => {
+                self.delete_list_status(caller, Media::Manga, id)
+            }
+
+            ("GET", ["users", name]) => self.user(req, caller, name),
+            ("GET", ["users", name, "animelist"]) => {
+                self.user_list(req, caller, Media::Anime, name)
+            }
+            ("GET", ["users", name, "mangalist"]) => {
+                self.user_list(req, caller, Media::Manga, name)
+            }
+
+            ("GET", ["forum", "boards"]) => Ok(self.fixtures.forum_boards.clone()),
+            ("GET", ["forum", "topics"]) => self.forum_topics(req),
+            ("GET", ["forum", "topic", id]) => self.forum_topic(req, id),
+
+            _ => Err(not_found()),
+        }
+    }
+
+    /// Check the credentials of the request.
+    fn caller(&self, req: &Request) -> Result<Caller, Response> {
+        if let Some(auth) = req.header("authorization") {
+            return match auth.strip_prefix("Bearer ") {
+                Some(token) if self.access_valid && token == self.access_token => Ok(Caller::User),
+                _ => Err(invalid_token()),
+            };
+        }
+
+        if req.header("x-mal-client-id") == Some(MOCK_CLIENT_ID) {
+            Ok(Caller::ClientId)
+        } else {
+            Err(error(StatusCode::UNAUTHORIZED, "invalid_client", ""))
+        }
+    }
+
+    fn nodes(&self, media: Media) -> &[Value] {
+        match media {
+            Media::Anime => &self.fixtures.anime,
+            Media::Manga => &self.fixtures.manga,
+        }
+    }
+
+    fn list(&self, media: Media) -> &BTreeMap<u64, Value> {
+        match media {
+            Media::Anime => &self.fixtures.animelist,
+            Media::Manga => &self.fixtures.mangalist,
+        }
+    }
+
+    fn list_mut(&mut self, media: Media) -> &mut BTreeMap<u64, Value> {
+        match media {
+            Media::Anime => &mut self.fixtures.animelist,
+            Media::Manga => &mut self.fixtures.mangalist,
+        }
+    }
+
+    fn node(&self, media: Media, id: u64) -> Option<&Value> {
+        self.nodes(media)
+            .iter()
+            .find(|node| node["id"].as_u64() == Some(id))
+    }
+
+    /// A node with only the requested fields.
+    fn node_view(
+        &self,
+        media: Media,
+        node: &Value,
+        fields: &HashSet<String>,
+        caller: Caller,
+    ) -> Value {
+        let mut view = select(node, fields, NODE_FIELDS);
+
+        if caller == Caller::User && fields.contains("my_list_status") {
+            let status = node["id"].as_u64().and_then(|id| self.list(media).get(&id));
+
+            if let (Some(status), Value::Object(view)) = (status, &mut view) {
+                view.insert("my_list_status".to_owned(), status.clone());
+            }
+        }
+
+        view
+    }
+
+    /// Page through `nodes`, returning them as `{ node }` items.
+    fn node_page(
+        &self,
+        req: &Request,
+        caller: Caller,
+        media: Media,
+        nodes: Vec<&Value>,
+        max_limit: usize,
+    ) -> Handled {
+        let query = req.query();
+        let fields = requested_fields(&query);
+
+        let (nodes, paging) = page(req, nodes, max_limit)?;
+        let data = nodes
+            .into_iter()
+            .map(|node| json!({ "node": self.node_view(media, node, &fields, caller) }))
+            .collect::<Vec<_>>();
+
+        Ok(json!({ "data": data, "paging": paging }))
+    }
+
+    fn search(&self, req: &Request, caller: Caller, media: Media) -> Handled {
+        let query = req.query();
+        let Some(q) = query.get("q").map(|q| q.to_lowercase()) else {
+            return Err(bad_request("invalid q"));
+        };
+
+        let nsfw = nsfw_allowed(&query);
+        let nodes = self
+            .nodes(media)
+            .iter()
+            .filter(|node| nsfw || is_sfw(node))
+            .filter(|node| {
+                let title = node["title"].as_str().unwrap_or_default();
+                let en = node["alternative_titles"]["en"]
+                    .as_str()
+                    .unwrap_or_default();
+
+                title.to_lowercase().contains(&q) || en.to_lowercase().contains(&q)
+            })
+            .collect();
+
+        self.node_page(req, caller, media, nodes, 100)
+    }
+
+    fn details(&self, req: &Request, caller: Caller, media: Media, id: &str) -> Handled {
+        let id = parse_id(id)?;
+        let Some(node) = self.node(media, id) else {
+            return Err(not_found());
+        };
+
+        let fields = requested_fields(&req.query());
+        Ok(self.node_view(media, node, &fields, caller))
+    }
+
+    fn ranking(&self, req: &Request, caller: Caller, media: Media) -> Handled {
+        let query = req.query();
+        let Some(ranking_type) = query.get("ranking_type") else {
+            return Err(bad_request("invalid ranking_type"));
+        };
+
+        let filter = |node: &Value| -> Option<bool> {
+            let media_type = node["media_type"].as_str().unwrap_or_default();
+            let status = node["status"].as_str().unwrap_or_default();
+
+            let keep = match (media, ranking_type.as_str()) {
+                (_, "all" | "bypopularity" | "favorite") => true,
+                (Media::Anime, "airing") => status == "currently_airing",
+                (Media::Anime, "upcoming") => status == "not_yet_aired",
+                (Media::Anime, "tv" | "ova" | "movie" | "special") => media_type == ranking_type,
+                (Media::Manga, "manga" | "manhwa" | "manhua") => media_type == ranking_type,
+                (Media::Manga, "novels") => matches!(media_type, "novel" | "light_novel"),
+                (Media::Manga, "oneshots") => media_type == "one_shot",
+                (Media::Manga, "doujin") => media_type == "doujinshi",
+                _ => return None,
+            };
+
+            Some(keep)
+        };
+
+        let mut nodes = Vec::new();
+        for node in self.nodes(media) {
+            match filter(node) {
+                Some(true) => nodes.push(node),
+                Some(false) => (),
+                None => return Err(bad_request("invalid ranking_type")),
+            }
+        }
+
+        let key = if ranking_type == "bypopularity" {
+            "popularity"
+        } else {
+            "rank"
+        };
+        sort_by(&mut nodes, |node| &node[key], false);
+
+        let fields = requested_fields(&query);
+        let ranked = nodes.into_iter().enumerate().collect::<Vec<_>>();
+        let (ranked, paging) = page(req, ranked, 500)?;
+
+        let data = ranked
+            .into_iter()
+            .map(|(i, node)| {
+                json!({
+                    "node": self.node_view(media, node, &fields, caller),
+                    "ranking": { "rank": i + 1 },
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Ok(json!({ "data": data, "paging": paging }))
+    }
+
+    fn seasonal(&self, req: &Request, caller: Caller, year: &str, season: &str) -> Handled {
+        let Ok(year) = year.parse::<u64>() else {
+            return Err(bad_request("invalid year"));
+        };
+
+        if !["winter", "spring", "summer", "fall"].contains(&season) {
+            return Err(bad_request("invalid season"));
+        }
+
+        let query = req.query();
+        let nsfw = nsfw_allowed(&query);
+
+        let mut nodes = self
+            .nodes(Media::Anime)
+            .iter()
+            .filter(|node| nsfw || is_sfw(node))
+            .filter(|node| {
+                let start = &node["start_season"];
+                start["year"].as_u64() == Some(year) && start["season"] == season
+            })
+            .collect::<Vec<_>>();
+
+        match query.get("sort").map(String::as_str) {
+            None => (),
+            Some("anime_score") => sort_by(&mut nodes, |node| &node["mean"], true),
+            Some("anime_num_list_users") => {
+                sort_by(&mut nodes, |node| &node["num_list_users"], true)
+            }
+            Some(_) => return Err(bad_request("invalid sort")),
+        }
+
+        let mut list = self.node_page(req, caller, Media::Anime, nodes, 500)?;
+        list["season"] = json!({ "year": year, "season": season });
+
+        Ok(list)
+    }
+
+    fn suggestions(&self, req: &Request, caller: Caller) -> Handled {
+        require_user(caller)?;
+
+        let nsfw = nsfw_allowed(&req.query());
+        let list = self.list(Media::Anime);
+
+        let nodes = self
+            .nodes(Media::Anime)
+            .iter()
+            .filter(|node| nsfw || is_sfw(node))
+            .filter(|node| node["id"].as_u64().is_none_or(|id| !list.contains_key(&id)))
+            .collect();
+
+        self.node_page(req, caller, Media::Anime, nodes, 100)
+    }
+
+    fn user(&self, req: &Request, caller: Caller, name: &str) -> Handled {
+        // mal only supports @me here
+        if name != "@me" {
+            return Err(not_found());
+        }
+
+        require_user(caller)?;
+
+        let fields = requested_fields(&req.query());
+        Ok(select(&self.fixtures.user, &fields, USER_FIELDS))
+    }
+
+    fn user_list(&self, req: &Request, caller: Caller, media: Media, name: &str) -> Handled {
+        match name {
+            "@me" => require_user(caller)?,
+            name if self.fixtures.user["name"] == name => (),
+            _ => return Err(not_found()),
+        }
+
+        let query = req.query();
+        let fields = requested_fields(&query);
+        let nsfw = nsfw_allowed(&query);
+        let status = query.get("status");
+
+        let mut items = self
+            .list(media)
+            .iter()
+            .filter(|(_, entry)| status.is_none_or(|status| entry["status"] == **status))
+            .filter_map(|(id, entry)| Some((self.node(media, *id)?, entry)))
+            .filter(|(node, _)| nsfw || is_sfw(node))
+            .collect::<Vec<_>>();
+
+        let name = media.name();
+        match query.get("sort").map(String::as_str) {
+            None => (),
+            Some("list_score") => sort_by(&mut items, |(_, entry)| &entry["score"], true),
+            Some("list_updated_at") => sort_by(&mut items, |(_, entry)| &entry["updated_at"], true),
+            Some(sort) if sort == format!("{name}_title") => {
+                sort_by(&mut items, |(node, _)| &node["title"], false)
+            }
+            Some(sort) if sort == format!("{name}_start_date") => {
+                sort_by(&mut items, |(node, _)| &node["start_date"], true)
+            }
+            Some(sort) if sort == format!("{name}_id") => {
+                sort_by(&mut items, |(node, _)| &node["id"], false)
+            }
+            Some(_) => return Err(bad_request("invalid sort")),
+        }
+
+        let (items, paging) = page(req, items, 1000)?;
+        let data = items
+            .into_iter()
+            .map(|(node, entry)| {
+                json!({
+                    "node": self.node_view(media, node, &fields, caller),
+                    "list_status": entry,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Ok(json!({ "data": data, "paging": paging }))
+    }
+
+    fn put_list_status(
+        &mut self,
+        req: &Request,
+        caller: Caller,
+        media: Media,
+        id: &str,
+    ) -> Handled {
+        require_user(caller)?;
+
+        let id = parse_id(id)?;
+        if self.node(media, id).is_none() {
+            return Err(not_found());
+        }
+
+        let spec = media.list_spec();
+        let form = req.form();
+
+        let mut entry = self.list(media).get(&id).cloned().unwrap_or_else(|| {
+            let mut entry = json!({
+                "status": spec.default_status,
+                "tags": [],
+                "comments": "",
+            });
+
+            entry[spec.flag] = json!(false);
+            for (_, field) in spec.numbers {
+                entry[*field] = json!(0);
+            }
+
+            entry
+        });
+
+        if let Some(status) = form.get("status") {
+            if !spec.statuses.contains(&status.as_str()) {
+                return Err(bad_request("invalid status"));
+            }
+
+            entry["status"] = json!(status);
+        }
+
+        if let Some(flag) = form.get(spec.flag) {
+            let Ok(flag) = flag.parse::<bool>() else {
+                return Err(bad_request(&format!("invalid {}", spec.flag)));
+            };
+
+            entry[spec.flag] = json!(flag);
+        }
+
+        for (param, field) in spec.numbers {
+            if let Some(value) = form.get(*param) {
+                let Ok(value) = value.parse::<u64>() else {
+                    return Err(bad_request(&format!("invalid {param}")));
+                };
+
+                entry[*field] = json!(value);
+            }
+        }
+
+        if let Some(tags) = form.get("tags") {
+            let tags = tags
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .collect::<Vec<_>>();
+
+            entry["tags"] = json!(tags);
+        }
+
+        if let Some(comments) = form.get("comments") {
+            entry["comments"] = json!(comments);
+        }
+
+        entry["updated_at"] = json!(Utc::now().to_rfc3339());
+
+        self.list_mut(media).insert(id, entry.clone());
+
+        Ok(entry)
+    }
+
+    fn delete_list_status(&mut self, caller: Caller, media: Media, id: &str) -> Handled {
+        require_user(caller)?;
+
+        let id = parse_id(id)?;
+        match self.list_mut(media).remove(&id) {
+            Some(_) => Ok(json!([])),
+            None => Err(not_found()),
+        }
+    }
+
+    fn forum_topics(&self, req: &Request) -> Handled {
+        let query = req.query();
+
+        let id_param = |name: &str| -> Result<Option<u64>, Response> {
+            query
+                .get(name)
+                .map(|id| {
+                    id.parse()
+                        .map_err(|_| bad_request(&format!("invalid {name}")))
+                })
+                .transpose()
+        };
+
+        let board_id = id_param("board_id")?;
+        let subboard_id = id_param("subboard_id")?;
+        let q = query.get("q").map(|q| q.to_lowercase());
+        let topic_user_name = query.get("topic_user_name");
+        let user_name = query.get("user_name");
+
+        let mut topics = self
+            .fixtures
+            .forum_topics
+            .iter()
+            .filter(|topic| board_id.is_none_or(|id| topic["board_id"].as_u64() == Some(id)))
+            .filter(|topic| subboard_id.is_none_or(|id| topic["subboard_id"].as_u64() == Some(id)))
+            .filter(|topic| {
+                q.as_ref().is_none_or(|q| {
+                    let title = topic["title"].as_str().unwrap_or_default();
+                    title.to_lowercase().contains(q)
+                })
+            })
+            .filter(|topic| {
+                topic_user_name.is_none_or(|name| topic["created_by"]["name"] == **name)
+            })
+            .filter(|topic| {
+                user_name.is_none_or(|name| {
+                    topic["created_by"]["name"] == **name
+                        || topic["last_post_created_by"]["name"] == **name
+                })
+            })
+            .collect::<Vec<_>>();
+
+        match query.get("sort").map(String::as_str) {
+            None => (),
+            Some("recent") => sort_by(&mut topics, |topic| &topic["last_post_created_at"], true),
+            Some(_) => return Err(bad_request("invalid sort")),
+        }
+
+        let (topics, paging) = page(req, topics, 100)?;
+
+        Ok(json!({ "data": topics, "paging": paging }))
+    }
+
+    fn forum_topic(&self, req: &Request, id: &str) -> Handled {
+        let id = parse_id(id)?;
+        let Some(topic) = self.fixtures.topics.get(&id) else {
+            return Err(not_found());
+        };
+
+        let posts = topic["posts"]
+            .as_array()
+            .map(|posts| posts.iter().collect())
+            .unwrap_or_default();
+
+        let (posts, paging) = page(req, posts, 100)?;
+
+        let mut data = topic.clone();
+        data["posts"] = json!(posts);
+
+        Ok(json!({ "data": data, "paging": paging }))
+    }
+
+    /// The oauth2 token endpoint, for both authorization codes and refresh tokens.
+    fn token(&mut self, req: &Request) -> Response {
+        let form = req.form();
+
+        if req.header("authorization").is_none() && !form.contains_key("client_id") {
+            return oauth_error(StatusCode::UNAUTHORIZED, "invalid_client");
+        }
+
+        match form.get("grant_type").map(String::as_str) {
+            Some("authorization_code") if form.contains_key("code") => (),
+            Some("authorization_code") => {
+                return oauth_error(StatusCode::BAD_REQUEST, "invalid_request");
+            }
+            Some("refresh_token") if form.get("refresh_token") == Some(&self.refresh_token) => (),
+            Some("refresh_token") => return oauth_error(StatusCode::BAD_REQUEST, "invalid_grant"),
+            _ => return oauth_error(StatusCode::BAD_REQUEST, "unsupported_grant_type"),
+        }
+
+        self.generation += 1;
+        self.access_token = format!("{MOCK_ACCESS_TOKEN}-{}", self.generation);
+        self.refresh_token = format!("{MOCK_REFRESH_TOKEN}-{}", self.generation);
+        self.access_valid = true;
+        self.expires_at = now() + EXPIRES_IN;
+
+        let body = json!({
+            "token_type": "Bearer",
+            "expires_in": EXPIRES_IN,
+            "access_token": self.access_token,
+            "refresh_token": self.refresh_token,
+        });
+
+        Response::json(StatusCode::OK, &body)
+    }
+}
+
+fn now() -> u64 {
+    Utc::now().timestamp() as u64
+}
+
+fn error(status: StatusCode, error: &str, message: &str) -> Response {
+    Response::json(status, &json!({ "error": error, "message": message }))
+}
+
+fn bad_request(message: &str) -> Response {
+    error(StatusCode::BAD_REQUEST, "bad_request", message)
+}
+
+fn not_found() -> Response {
+    error(StatusCode::NOT_FOUND, "not_found", "")
+}
+
+fn invalid_token() -> Response {
+    error(StatusCode::UNAUTHORIZED, "invalid_token", "")
+        .header("WWW-Authenticate", "Bearer error=\"invalid_token\"")
+}
+
+fn oauth_error(status: StatusCode, error: &str) -> Response {
+    Response::json(status, &json!({ "error": error }))
+}
+
+fn require_user(caller: Caller) -> Result<(), Response> {
+    match caller {
+        Caller::User => Ok(()),
+        Caller::ClientId => Err(invalid_token()),
+    }
+}
+
+fn parse_id(id: &str) -> Result<u64, Response> {
+    id.parse().map_err(|_| bad_request("invalid id"))
+}
+
+fn nsfw_allowed(query: &HashMap<String, String>) -> bool {
+    query.get("nsfw").is_some_and(|nsfw| nsfw == "true")
+}
+
+fn is_sfw(node: &Value) -> bool {
+    matches!(node["nsfw"].as_str(), None | Some("white"))
+}
+
+/// The top level names of the `fields` parameter, without their `{sub,fields}`.
+fn requested_fields(query: &HashMap<String, String>) -> HashSet<String> {
+    let Some(fields) = query.get("fields") else {
+        return HashSet::new();
+    };
+
+    let mut names = HashSet::new();
+    let mut name = String::new();
+    let mut depth = 0usize;
+
+    for c in fields.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                names.insert(mem::take(&mut name).trim().to_owned());
+            }
+            c if depth == 0 => name.push(c),
+            _ => (),
+        }
+    }
+
+    names.insert(name.trim().to_owned());
+    names.remove("");
+
+    names
+}
+
+/// Keep the fields of `value` which are in `defaults` or were requested.
+fn select(value: &Value, fields: &HashSet<String>, defaults: &[&str]) -> Value {
+    let Value::Object(object) = value else {
+        return value.clone();
+    };
+
+    let selected = object
+        .iter()
+        .filter(|(key, _)| defaults.contains(&key.as_str()) || fields.contains(*key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect::<Map<_, _>>();
+
+    Value::Object(selected)
+}
+
+/// Sort by a json value. Missing values always come last.
+fn sort_by<T>(items: &mut [T], key: impl Fn(&T) -> &Value, descending: bool) {
+    items.sort_by(|a, b| {
+        let (a, b) = (key(a), key(b));
+
+        match (a.is_null(), b.is_null()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            _ if descending => compare(b, a),
+            _ => compare(a, b),
+        }
+    });
+}
+
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    }
+}
+
+/// Take one page of `items` according to the `limit` and `offset` parameters.
+///
+/// Returns the page and the `paging` object linking to the previous and next pages.
+fn page<T>(req: &Request, items: Vec<T>, max_limit: usize) -> Result<(Vec<T>, Value), Response> {
+    let query = req.query();
+
+    let param = |name: &str, default: usize| -> Result<usize, Response> {
+        match query.get(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| bad_request(&format!("invalid {name}"))),
+            None => Ok(default),
+        }
+    };
+
+    let limit = param("limit", 100.min(max_limit))?;
+    let offset = param("offset", 0)?;
+
+    if limit == 0 || limit > max_limit {
+        return Err(bad_request("invalid limit"));
+    }
+
+    let total = items.len();
+    let items = items.into_iter().skip(offset).take(limit).collect();
+
+    let mut paging = Map::new();
+    if offset > 0 {
+        let previous = page_url(req, offset.saturating_sub(limit));
+        paging.insert("previous".to_owned(), json!(previous));
+    }
+
+    let next = offset.saturating_add(limit);
+    if next < total {
+        paging.insert("next".to_owned(), json!(page_url(req, next)));
+    }
+
+    Ok((items, Value::Object(paging)))
+}
+
+/// The url of the request with a different `offset`.
+fn page_url(req: &Request, offset: usize) -> String {
+    let mut url = req.url.clone();
+
+    if let Some(host) = req.header("host") {
+        _ = url.set_host(Some(host.split(':').next().unwrap_or(host)));
+        _ = url.set_port(host.split(':').nth(1).and_then(|port| port.parse().ok()));
+    }
+
+    let query = req
+        .query()
+        .into_iter()
+        .filter(|(key, _)| key != "offset")
+        .collect::<BTreeMap<_, _>>();
+
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair("offset", &offset.to_string());
+
+    url.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::TryStreamExt as _;
+
+    use super::*;
+    use crate::{
+        api_request::ApiError,
+        auth::{MemoryTokenStore, SharedAuth, TokenStore as _},
+        objects::{AnimeId, AnimeRankingType, WatchStatus},
+        MalClientError,
+    };
+
+    #[tokio::test]
+    async fn serves_anime_details() {
+        let server = MockServer::start().await.unwrap();
+        let client = server.client();
+
+        let anime = client
+            .anime()
+            .get()
+            .details()
+            .anime_id(5114)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(anime.id, AnimeId(5114));
+        assert!(!anime.title.is_empty());
+
+        let missing = client.anime().get().details().anime_id(1).send().await;
+        assert!(matches!(missing, Err(ApiError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn follows_every_page() {
+        let server = MockServer::start().await.unwrap();
+        let client = server.client();
+
+        let items = client
+            .anime()
+            .get()
+            .ranking()
+            .ranking_type(AnimeRankingType::All)
+            .limit(1)
+            .into_stream()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let ranks = items
+            .iter()
+            .map(|item| item.ranking.rank)
+            .collect::<Vec<_>>();
+
+        assert_eq!(ranks, [1, 2, 3, 4]);
+        assert_eq!(server.request_count(), 4);
+    }
+
+    #[tokio::test]
+    async fn huge_offset_is_an_empty_page() {
+        let server = MockServer::start().await.unwrap();
+
+        let page = server
+            .client()
+            .anime()
+            .get()
+            .ranking()
+            .ranking_type(AnimeRankingType::All)
+            .offset(u64::MAX)
+            .send()
+            .await
+            .unwrap();
+
+        assert!(page.data.is_empty());
+        assert!(page.paging.and_then(|paging| paging.next).is_none());
+    }
+
+    #[tokio::test]
+    async fn updates_and_deletes_list_entries() {
+        let server = MockServer::start().await.unwrap();
+        let client = server.client();
+
+        let entry = client
+            .user_animelist()
+            .put()
+            .anime_id(21)
+            .status(WatchStatus::Watching)
+            .num_watched_episodes(3)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(entry.status, WatchStatus::Watching);
+        assert_eq!(entry.num_episodes_watched, 3);
+        assert_eq!(server.animelist()[&21]["num_episodes_watched"], 3);
+
+        client
+            .user_animelist()
+            .delete()
+            .anime_id(21)
+            .send()
+            .await
+            .unwrap();
+
+        assert!(!server.animelist().contains_key(&21));
+
+        let again = client.user_animelist().delete().anime_id(21).send().await;
+        assert!(matches!(again, Err(ApiError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn refreshes_rejected_access_token() {
+        let server = MockServer::start().await.unwrap();
+        let client = server.client_builder().auth(server.auth()).build().unwrap();
+
+        server.expire_access_token();
+
+        let user = client.user().get().information().send().await;
+        assert!(user.is_ok());
+
+        let tokens = client.tokens();
+        assert_eq!(
+            tokens.access_token.secret(),
+            server.tokens().access_token.secret()
+        );
+        assert_ne!(tokens.refresh_token.secret(), MOCK_REFRESH_TOKEN);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_client_id() {
+        let server = MockServer::start().await.unwrap();
+        let client = server
+            .client_builder()
+            .client_id(ClientId::new("unknown".to_owned()))
+            .build()
+            .unwrap();
+
+        let result = client.anime().get().details().anime_id(5114).send().await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn saves_refreshed_tokens_to_store() {
+        let server = MockServer::start().await.unwrap();
+        let store = Arc::new(MemoryTokenStore::new());
+        let client = server
+            .client_builder()
+            .auth(server.auth())
+            .token_store(store.clone())
+            .build()
+            .unwrap();
+
+        server.expire_access_token();
+        client.user().get().information().send().await.unwrap();
+
+        let saved = store.load().await.unwrap().unwrap();
+        assert_eq!(
+            saved.access_token.secret(),
+            server.tokens().access_token.secret()
+        );
+    }
+
+    #[tokio::test]
+    async fn token_store_needs_auth() {
+        let server = MockServer::start().await.unwrap();
+
+        let without_auth = server
+            .client_builder()
+            .token_store(MemoryTokenStore::new())
+            .build();
+        assert!(matches!(
+            without_auth,
+            Err(MalClientError::Conflict(message)) if message == "token_store requires auth"
+        ));
+
+        let with_shared_auth = server
+            .client_builder()
+            .auth(server.auth())
+            .shared_auth(SharedAuth::new(server.auth()))
+            .token_store(MemoryTokenStore::new())
+            .build();
+        assert!(matches!(
+            with_shared_auth,
+            Err(MalClientError::Conflict(message)) if message.contains("shared_auth")
+        ));
+    }
+}
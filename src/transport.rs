@@ -0,0 +1,144 @@
+use std::{error::Error as StdError, future::Future, pin::Pin, sync::Arc};
+
+use reqwest::{header::HeaderMap, Client, Method, StatusCode, Url};
+use thiserror::Error;
+
+/// A boxed error from a custom [HttpTransport].
+pub type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// The future returned by [HttpTransport::send].
+pub type TransportFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<HttpResponse, TransportError>> + Send + 'a>>;
+
+/// A request to the mal api.
+///
+/// The credentials are already set in the headers.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: Url,
+    pub headers: HeaderMap,
+    pub body: Option<Vec<u8>>,
+}
+
+/// The response to a [HttpRequest].
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// Sends the requests of a [MalClient](crate::MalClient).
+///
+/// By default, requests are sent with a [ReqwestTransport]. Implement this to use your own
+/// http client (e.g. an instrumented one, or a mock), and set it with
+/// [`MalClientBuilder::transport()`](crate::MalClientBuilder::transport).
+///
+/// Retries, rate limiting and token refreshing happen before the request gets here,
+/// so a transport only needs to send the request once.
+///
+/// ```rust,ignore
+/// struct Logged(ReqwestTransport);
+///
+/// impl HttpTransport for Logged {
+///     fn send(&self, request: HttpRequest) -> TransportFuture<'_> {
+///         println!("{} {}", request.method, request.url);
+///         self.0.send(request)
+///     }
+/// }
+/// ```
+pub trait HttpTransport: Send + Sync {
+    /// Send the request and read the whole response.
+    ///
+    /// An error status is not an error here, it must be returned as a [HttpResponse].
+    fn send(&self, request: HttpRequest) -> TransportFuture<'_>;
+}
+
+impl<T: HttpTransport + ?Sized> HttpTransport for Arc<T> {
+    fn send(&self, request: HttpRequest) -> TransportFuture<'_> {
+        (**self).send(request)
+    }
+}
+
+/// A [HttpTransport] sending requests with a [reqwest::Client].
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    client: Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// The client requests are sent with.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl HttpTransport for ReqwestTransport {
+    fn send(&self, request: HttpRequest) -> TransportFuture<'_> {
+        Box::pin(async move {
+            let HttpRequest {
+                method,
+                url,
+                headers,
+                body,
+            } = request;
+
+            let mut request = self.client.request(method, url).headers(headers);
+            if let Some(body) = body {
+                request = request.body(body);
+            }
+
+            let response = request.send().await?;
+
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = response.bytes().await?.to_vec();
+
+            Ok(HttpResponse {
+                status,
+                headers,
+                body,
+            })
+        })
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum TransportError {
+    #[error("{0}")]
+    Reqwest(#[from] reqwest::Error),
+    /// The request couldn't reach mal (connection refused/reset, dns failures, timeouts).
+    ///
+    /// These are retried, see [RetryPolicy::retry_connection_errors](crate::retry::RetryPolicy::retry_connection_errors).
+    #[error("{0}")]
+    Connection(#[source] BoxError),
+    #[error("{0}")]
+    Other(#[source] BoxError),
+}
+
+impl TransportError {
+    /// The request couldn't reach mal. See [TransportError::Connection].
+    pub fn connection(error: impl Into<BoxError>) -> Self {
+        Self::Connection(error.into())
+    }
+
+    /// Any other failure.
+    pub fn other(error: impl Into<BoxError>) -> Self {
+        Self::Other(error.into())
+    }
+
+    /// Whether the request couldn't reach mal, or timed out.
+    pub fn is_connection_error(&self) -> bool {
+        match self {
+            Self::Reqwest(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+            Self::Connection(_) => true,
+            Self::Other(_) => false,
+        }
+    }
+}